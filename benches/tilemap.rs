@@ -0,0 +1,178 @@
+use bevy::{
+    asset::AssetPlugin,
+    ecs::system::CommandQueue,
+    prelude::*,
+    render::{
+        camera::ScalingMode,
+        view::{check_visibility, update_frusta, visibility_propagate_system},
+    },
+    transform::systems::transform_propagate_system,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use noob_bevy::{
+    ascii::{spawn_ascii_sprite, AsciiSpriteSheet},
+    tile_chunks::{spawn_tile_chunks, AsciiChunkMaterial, ChunkTile, TileChunkMaterial},
+    RESOLUTION, TILE_SIZE,
+};
+
+//a 256x256 map, the size where a sprite per tile gets slow
+const MAP_SIZE: i32 = 256;
+
+fn map_tiles() -> Vec<ChunkTile> {
+    (0..MAP_SIZE)
+        .flat_map(|y| (0..MAP_SIZE).map(move |x| IVec2::new(x, y)))
+        .map(|tile| ChunkTile {
+            tile,
+            index: if (tile.x * 7 + tile.y * 3) % 5 == 0 {
+                '"' as usize
+            } else {
+                '.' as usize
+            },
+            color: Color::rgb(0.2, 0.9, 0.2),
+            animation: None,
+        })
+        .collect()
+}
+
+fn ascii_atlas() -> TextureAtlas {
+    //same layout as Ascii.png, the image itself is never needed to build meshes
+    TextureAtlas::from_grid_with_padding(
+        Handle::default(),
+        Vec2::splat(9.0),
+        16,
+        16,
+        Vec2::splat(2.0),
+        Vec2::ZERO,
+    )
+}
+
+//the world a map lives in, with mesh assets for the chunks and the game camera in the middle of the map
+fn empty_world() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<TileChunkMaterial>();
+
+    let mut camera = Camera2dBundle::default();
+    camera.projection.top = 1.;
+    camera.projection.bottom = -1.;
+    camera.projection.left = -1. * RESOLUTION;
+    camera.projection.right = 1. * RESOLUTION;
+    camera.projection.scaling_mode = ScalingMode::None;
+    let middle = MAP_SIZE as f32 / 2.0 * TILE_SIZE;
+    camera.transform.translation.x = middle;
+    camera.transform.translation.y = -middle;
+    app.world.spawn().insert_bundle(camera);
+    app
+}
+
+//the part of a frame that grows with the number of tile entities, short of extracting and drawing them:
+//transforms, visibility down the hierarchy and culling against the camera
+//sprites have no aabb in this bevy version, so every sprite tile is sent on as visible while chunks off screen are culled
+fn frame_stage() -> SystemStage {
+    SystemStage::single_threaded()
+        .with_system(transform_propagate_system.label("transforms"))
+        .with_system(
+            update_frusta::<OrthographicProjection>
+                .label("frusta")
+                .after("transforms"),
+        )
+        .with_system(visibility_propagate_system.label("visibility"))
+        .with_system(check_visibility.after("frusta").after("visibility"))
+}
+
+//the way maps were spawned before chunks, one SpriteSheetBundle per tile
+fn spawn_sprites(world: &mut World, tiles: &[ChunkTile]) {
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+    let ascii = AsciiSpriteSheet(Handle::default());
+    let sprites: Vec<Entity> = tiles
+        .iter()
+        .map(|tile| {
+            spawn_ascii_sprite(
+                &mut commands,
+                &ascii,
+                tile.index,
+                tile.color,
+                Vec3::new(
+                    tile.tile.x as f32 * TILE_SIZE,
+                    -(tile.tile.y as f32) * TILE_SIZE,
+                    100.0,
+                ),
+                Vec3::splat(1.0),
+            )
+        })
+        .collect();
+    commands
+        .spawn_bundle(SpatialBundle::default())
+        .push_children(&sprites);
+    queue.apply(world);
+}
+
+fn spawn_chunks(world: &mut World, tiles: &[ChunkTile], atlas: &TextureAtlas) {
+    let mut queue = CommandQueue::default();
+    world.resource_scope(|world, mut meshes: Mut<Assets<Mesh>>| {
+        let mut commands = Commands::new(&mut queue, world);
+        let material = AsciiChunkMaterial(Handle::default());
+        let chunks = spawn_tile_chunks(
+            &mut commands,
+            &mut meshes,
+            &material,
+            atlas,
+            tiles,
+            &[],
+            100.0,
+        );
+        commands
+            .spawn_bundle(SpatialBundle::default())
+            .push_children(&chunks);
+    });
+    queue.apply(world);
+}
+
+fn spawn(c: &mut Criterion) {
+    let tiles = map_tiles();
+    let atlas = ascii_atlas();
+    let mut group = c.benchmark_group("spawn 256x256 map");
+    group.sample_size(20);
+
+    group.bench_function("sprite per tile", |b| {
+        b.iter_batched(
+            empty_world,
+            |mut app| spawn_sprites(&mut app.world, &tiles),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("chunk meshes", |b| {
+        b.iter_batched(
+            empty_world,
+            |mut app| spawn_chunks(&mut app.world, &tiles, &atlas),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn frame(c: &mut Criterion) {
+    let tiles = map_tiles();
+    let atlas = ascii_atlas();
+    let mut group = c.benchmark_group("transforms and culling with a 256x256 map");
+
+    let mut sprites = empty_world();
+    spawn_sprites(&mut sprites.world, &tiles);
+    let mut stage = frame_stage();
+    group.bench_function("sprite per tile", |b| {
+        b.iter(|| stage.run(&mut sprites.world))
+    });
+
+    let mut chunks = empty_world();
+    spawn_chunks(&mut chunks.world, &tiles, &atlas);
+    let mut stage = frame_stage();
+    group.bench_function("chunk meshes", |b| b.iter(|| stage.run(&mut chunks.world)));
+    group.finish();
+}
+
+criterion_group!(benches, spawn, frame);
+criterion_main!(benches);
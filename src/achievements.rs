@@ -0,0 +1,367 @@
+use bevy::{prelude::*, transform::TransformSystem};
+use directories::ProjectDirs;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+};
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet, NineSliceIndices},
+    battle::EnemyType,
+    input::{Action, InputDevice, KeyBindings},
+    npc::spawn_text_panel,
+    player::Player,
+    GameState, MainCamera, RESOLUTION, TILE_SIZE,
+};
+
+pub struct AchievementsPlugin;
+
+//what has to happen for an achievement to unlock, counted over every playthrough
+#[derive(Clone, Deserialize)]
+pub enum Goal {
+    //no enemy type means any enemy counts
+    Defeat(Option<EnemyType>, usize),
+    ReachLevel(usize),
+    FlawlessWins(usize),
+    Flee(usize),
+}
+
+impl Goal {
+    fn target(&self) -> usize {
+        match self {
+            Goal::Defeat(_, count)
+            | Goal::ReachLevel(count)
+            | Goal::FlawlessWins(count)
+            | Goal::Flee(count) => *count,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Achievement {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub goal: Goal,
+}
+
+//loaded from assets/achievements.ron
+#[derive(Default)]
+pub struct Achievements(pub Vec<Achievement>);
+
+//unlocks belong to whoever plays on this machine, not to a save slot, so they live in their own file
+#[derive(Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub unlocked: BTreeSet<String>,
+    pub progress: BTreeMap<String, usize>,
+}
+
+//things that happened in the game that achievements are counted from
+pub enum AchievementEvent {
+    EnemyDefeated {
+        enemy_type: EnemyType,
+        damage_taken: usize,
+    },
+    LevelReached(usize),
+    Fled,
+}
+
+#[derive(Component)]
+struct Toast {
+    timer: Timer,
+}
+
+#[derive(Component)]
+struct AchievementsText;
+
+const TOAST_TIME: f32 = 3.0;
+const PROGRESS_BAR_WIDTH: usize = 10;
+
+impl Plugin for AchievementsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_achievements())
+            .insert_resource(Profile::load())
+            .add_event::<AchievementEvent>()
+            .add_system(track_achievements)
+            //toasts follow the camera in every state, so they are placed after everything else moved it
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                update_toasts.before(TransformSystem::TransformPropagate),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Overworld).with_system(open_achievements_screen),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Achievements)
+                    .with_system(achievements_screen_input)
+                    .with_system(draw_achievements_screen),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Achievements).with_system(despawn_achievements_text),
+            );
+    }
+}
+
+fn load_achievements() -> Achievements {
+    let path = "assets/achievements.ron";
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("Could not read {}: {}", path, err);
+            return Achievements::default();
+        }
+    };
+    match ron::from_str::<Vec<Achievement>>(&contents) {
+        Ok(achievements) => Achievements(achievements),
+        Err(err) => {
+            warn!("Could not parse {}: {}", path, err);
+            Achievements::default()
+        }
+    }
+}
+
+fn profile_path() -> PathBuf {
+    ProjectDirs::from("", "", "noob_bevy")
+        .map(|dirs| dirs.data_dir().join("profile.ron"))
+        .unwrap_or_else(|| PathBuf::from("profile.ron"))
+}
+
+impl Profile {
+    fn load() -> Self {
+        let path = profile_path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => ron::from_str(&contents).unwrap_or_else(|err| {
+                warn!("Could not parse {}: {}", path.display(), err);
+                Profile::default()
+            }),
+            //first time playing
+            Err(_) => Profile::default(),
+        }
+    }
+
+    fn save(&self) {
+        let path = profile_path();
+        let contents = match ron::ser::to_string_pretty(self, PrettyConfig::default()) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Could not serialize profile: {}", err);
+                return;
+            }
+        };
+
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents));
+        if let Err(err) = written {
+            error!("Could not write {}: {}", path.display(), err);
+        }
+    }
+
+    pub fn progress(&self, achievement: &Achievement) -> usize {
+        if self.unlocked.contains(&achievement.id) {
+            return achievement.goal.target();
+        }
+        self.progress
+            .get(&achievement.id)
+            .copied()
+            .unwrap_or(0)
+            .min(achievement.goal.target())
+    }
+}
+
+//how far the event moves an achievement, None when it does not count for it
+fn progress_after(goal: &Goal, current: usize, event: &AchievementEvent) -> Option<usize> {
+    match (goal, event) {
+        (Goal::Defeat(wanted, _), AchievementEvent::EnemyDefeated { enemy_type, .. })
+            if wanted.map_or(true, |wanted| wanted == *enemy_type) =>
+        {
+            Some(current + 1)
+        }
+        (Goal::ReachLevel(_), AchievementEvent::LevelReached(level)) => Some(current.max(*level)),
+        (
+            Goal::FlawlessWins(_),
+            AchievementEvent::EnemyDefeated {
+                damage_taken: 0, ..
+            },
+        ) => Some(current + 1),
+        (Goal::Flee(_), AchievementEvent::Fled) => Some(current + 1),
+        _ => None,
+    }
+}
+
+fn track_achievements(
+    mut commands: Commands,
+    mut events: EventReader<AchievementEvent>,
+    achievements: Res<Achievements>,
+    mut profile: ResMut<Profile>,
+    ascii: Res<AsciiSpriteSheet>,
+    indices: Res<NineSliceIndices>,
+) {
+    let mut changed = false;
+
+    for event in events.iter() {
+        for achievement in achievements.0.iter() {
+            if profile.unlocked.contains(&achievement.id) {
+                continue;
+            }
+            let current = profile.progress.get(&achievement.id).copied().unwrap_or(0);
+            let progress = match progress_after(&achievement.goal, current, event) {
+                Some(progress) => progress,
+                None => continue,
+            };
+            changed = true;
+
+            if progress >= achievement.goal.target() {
+                profile.progress.remove(&achievement.id);
+                profile.unlocked.insert(achievement.id.clone());
+                info!("Achievement unlocked: {}", achievement.name);
+
+                let text = format!("Achievement: {}", achievement.name);
+                let toast = spawn_text_panel(&mut commands, &ascii, &indices, Vec2::ZERO, &text);
+                commands
+                    .entity(toast)
+                    .insert(Toast {
+                        timer: Timer::from_seconds(TOAST_TIME, false),
+                    })
+                    .insert(Name::new("Achievement Toast"));
+            } else {
+                profile.progress.insert(achievement.id.clone(), progress);
+            }
+        }
+    }
+
+    if changed {
+        profile.save();
+    }
+}
+
+//toasts stack down from the top of the screen
+fn update_toasts(
+    mut commands: Commands,
+    mut toast_query: Query<(Entity, &mut Toast, &mut Transform), Without<MainCamera>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    time: Res<Time>,
+) {
+    let camera = camera_query.single().translation;
+    let mut shown = 0;
+    for (entity, mut toast, mut transform) in toast_query.iter_mut() {
+        toast.timer.tick(time.delta());
+        if toast.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        transform.translation.x = camera.x;
+        transform.translation.y = camera.y + 1.0 - 2.0 * TILE_SIZE - shown as f32 * 3.0 * TILE_SIZE;
+        transform.translation.z = 950.0;
+        shown += 1;
+    }
+}
+
+//cleared on both ends so the same press does not open and close the screen in one frame
+fn open_achievements_screen(
+    player_query: Query<&Player>,
+    mut actions: ResMut<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let player = player_query.single();
+    if player.active && actions.just_pressed(Action::Achievements) {
+        actions.clear();
+        let _ = state.push(GameState::Achievements);
+    }
+}
+
+fn achievements_screen_input(
+    mut actions: ResMut<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if actions.any_just_pressed([Action::Cancel, Action::Achievements]) {
+        actions.clear();
+        let _ = state.pop();
+    }
+}
+
+fn progress_bar(progress: usize, target: usize) -> String {
+    let filled = if target == 0 {
+        PROGRESS_BAR_WIDTH
+    } else {
+        progress * PROGRESS_BAR_WIDTH / target
+    };
+    format!(
+        "[{}{}] {}/{}",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        progress,
+        target
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_achievements_screen(
+    mut commands: Commands,
+    achievements: Res<Achievements>,
+    profile: Res<Profile>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<AchievementsText>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    if !text_query.is_empty() && !profile.is_changed() && !device.is_changed() {
+        return;
+    }
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let camera = camera_query.single().translation;
+    let left = camera.x - RESOLUTION + TILE_SIZE;
+    let top = camera.y + 1.0 - TILE_SIZE;
+
+    let unlocked = achievements
+        .0
+        .iter()
+        .filter(|achievement| profile.unlocked.contains(&achievement.id))
+        .count();
+    let mut lines = vec![
+        format!("Achievements {}/{}", unlocked, achievements.0.len()),
+        String::new(),
+    ];
+    for achievement in achievements.0.iter() {
+        let marker = if profile.unlocked.contains(&achievement.id) {
+            "*"
+        } else {
+            " "
+        };
+        lines.push(format!("{} {}", marker, achievement.name));
+        lines.push(format!(
+            "  {:<26}{}",
+            achievement.description,
+            progress_bar(profile.progress(achievement), achievement.goal.target())
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!("{} back", bindings.prompt(Action::Cancel, *device)));
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = spawn_ascii_text(
+            &mut commands,
+            &ascii,
+            line,
+            Vec3::new(left, top - i as f32 * TILE_SIZE, 900.0),
+        );
+        commands.entity(text).insert(AchievementsText);
+    }
+}
+
+fn despawn_achievements_text(
+    mut commands: Commands,
+    text_query: Query<Entity, With<AchievementsText>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
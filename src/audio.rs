@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use bevy_kira_audio::prelude::*;
 
-use crate::{battle::BattleState, battle::FightEvent, GameState};
+use crate::{battle::BattleState, battle::FightEvent, input::Action, GameState};
 
 pub struct GameAudioPlugin;
 
@@ -94,16 +94,16 @@ fn start_battle_music(
 }
 
 fn volume_control(
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
     background: Res<AudioChannel<BackgroundChannel>>,
     battle: Res<AudioChannel<BattleChannel>>,
     sfx: Res<AudioChannel<SfxChannel>>,
     mut audio_state: ResMut<AudioState>,
 ) {
-    if keyboard.just_pressed(KeyCode::Up) {
+    if actions.just_pressed(Action::VolumeUp) {
         audio_state.volume += 0.10;
     }
-    if keyboard.just_pressed(KeyCode::Down) {
+    if actions.just_pressed(Action::VolumeDown) {
         audio_state.volume -= 0.10;
     }
     audio_state.volume = audio_state.volume.clamp(0.0, 1.0);
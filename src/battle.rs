@@ -1,15 +1,28 @@
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    ascii::{spawn_ascii_text, spawn_nine_slice, AsciiSpriteSheet, NineSlice, NineSliceIndices},
+    achievements::AchievementEvent,
+    ascii::{
+        spawn_ascii_text, spawn_nine_slice, AsciiSpriteSheet, AsciiText, NineSlice,
+        NineSliceIndices,
+    },
+    character_creation::Hero,
+    classes::{Classes, Job, LearnableSkill},
+    equipment::Equipped,
     fadeout::create_fadeout,
-    graphics::{spawn_enemy_sprite, CharacterSheet},
+    graphics::{spawn_enemy_sprite, AnimationState, CharacterAnimation, CharacterSheet},
+    input::{Action, InputDevice, KeyBindings},
+    play_stats::PlayStats,
     player::Player,
+    skill_tree::SkillBonuses,
+    tilemap::EnemyTable,
     GameState, MainCamera, RESOLUTION, TILE_SIZE,
 };
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum EnemyType {
     Bat,
     Ghost,
@@ -41,13 +54,18 @@ pub struct AttackEffects {
 pub struct BattleText;
 pub struct BattlePlugin;
 
+//damage the player took in the current battle, for wins without a scratch
+#[derive(Default)]
+struct BattleDamageTaken(usize);
+
 pub struct FightEvent {
     target: Entity,
+    //a negative amount heals the target, up to its max health
     damage_amount: isize,
     next_state: BattleState,
 }
 
-#[derive(Component, Inspectable)]
+#[derive(Component, Inspectable, Clone, Serialize, Deserialize)]
 pub struct BattleStats {
     pub health: isize,
     pub max_health: isize,
@@ -58,6 +76,7 @@ pub struct BattleStats {
 impl Plugin for BattlePlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<FightEvent>()
+            .init_resource::<BattleDamageTaken>()
             .add_state(BattleState::PlayerTurn)
             .insert_resource(AttackEffects {
                 timer: Timer::from_seconds(0.7, true),
@@ -68,12 +87,14 @@ impl Plugin for BattlePlugin {
             .insert_resource(BattleMenuSelection {
                 selected: BattleMenuOption::Fight,
             })
+            .init_resource::<ClassSkillsLeft>()
             .add_system_set(
                 SystemSet::on_update(BattleState::EnemyTurn(false)).with_system(process_enemy_turn),
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Battle)
                     .with_system(battle_input)
+                    .with_system(class_skill_input)
                     .with_system(battle_camera)
                     .with_system(highlight_battle_buttons)
                     .with_system(damage_calculation),
@@ -167,17 +188,39 @@ fn handle_attack_effects(
     }
 }
 
-fn set_starting_state(mut state: ResMut<State<BattleState>>) {
+fn set_starting_state(
+    mut state: ResMut<State<BattleState>>,
+    mut damage_taken: ResMut<BattleDamageTaken>,
+) {
     let _ = state.set(BattleState::PlayerTurn);
+    damage_taken.0 = 0;
 }
 
-const NUM_MENU_OPTIONS: isize = 2;
 #[derive(Component, PartialEq, Clone, Copy)]
 pub enum BattleMenuOption {
     Fight,
+    //skills of the class, up and down pick which one, the button goes away once all are used
+    ClassSkill,
+    //the ability equipped on the skill tree, the button goes away once used
+    Skill,
     Run,
 }
 
+//left to right, options without a button are skipped
+const MENU_ORDER: [BattleMenuOption; 4] = [
+    BattleMenuOption::Fight,
+    BattleMenuOption::ClassSkill,
+    BattleMenuOption::Skill,
+    BattleMenuOption::Run,
+];
+
+//class skills not used yet in this battle, the one shown on the button is picked
+#[derive(Default)]
+pub struct ClassSkillsLeft {
+    skills: Vec<LearnableSkill>,
+    picked: usize,
+}
+
 pub struct BattleMenuSelection {
     selected: BattleMenuOption,
 }
@@ -209,10 +252,16 @@ fn spawn_battle_button(
         .id()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_battle_menu(
     mut commands: Commands,
     ascii: Res<AsciiSpriteSheet>,
     nine_slice_indices: Res<NineSliceIndices>,
+    bonuses: Res<SkillBonuses>,
+    player_query: Query<&Player>,
+    classes: Res<Classes>,
+    job: Res<Job>,
+    mut class_skills: ResMut<ClassSkillsLeft>,
 ) {
     let box_height = 3.0;
     let box_center_y = -1.0 + box_height * TILE_SIZE / 2.0;
@@ -231,9 +280,56 @@ fn spawn_battle_menu(
         Vec2::new(run_width, box_height),
     );
 
+    let mut used_width = run_width;
+    if let Some(ability) = &bonuses.ability {
+        let skill_width = (ability.name.len() + 2) as f32;
+        let skill_center_x =
+            RESOLUTION - (used_width * TILE_SIZE) - (skill_width * TILE_SIZE / 2.0);
+        spawn_battle_button(
+            &mut commands,
+            &ascii,
+            &nine_slice_indices,
+            Vec3::new(skill_center_x, box_center_y, 100.0),
+            &ability.name,
+            BattleMenuOption::Skill,
+            Vec2::new(skill_width, box_height),
+        );
+        used_width += skill_width;
+    }
+
+    let level = player_query.single().level;
+    class_skills.skills = classes
+        .get(&job.class)
+        .skills_until(level)
+        .cloned()
+        .collect();
+    class_skills.picked = 0;
+    if let Some(first) = class_skills.skills.first() {
+        //wide enough for every name, the button is relabeled when another skill is picked
+        let longest = class_skills
+            .skills
+            .iter()
+            .map(|skill| skill.name.len())
+            .max()
+            .unwrap_or(0);
+        let class_width = (longest + 2) as f32;
+        let class_center_x =
+            RESOLUTION - (used_width * TILE_SIZE) - (class_width * TILE_SIZE / 2.0);
+        spawn_battle_button(
+            &mut commands,
+            &ascii,
+            &nine_slice_indices,
+            Vec3::new(class_center_x, box_center_y, 100.0),
+            &first.name,
+            BattleMenuOption::ClassSkill,
+            Vec2::new(class_width, box_height),
+        );
+        used_width += class_width;
+    }
+
     let fight_text = "Fight";
     let fight_width = (fight_text.len() + 2) as f32;
-    let fight_center_x = RESOLUTION - (run_width * TILE_SIZE) - (fight_width * TILE_SIZE / 2.0);
+    let fight_center_x = RESOLUTION - (used_width * TILE_SIZE) - (fight_width * TILE_SIZE / 2.0);
 
     spawn_battle_button(
         &mut commands,
@@ -249,11 +345,12 @@ fn spawn_battle_menu(
 fn process_enemy_turn(
     mut fight_event: EventWriter<FightEvent>,
     mut battle_state: ResMut<State<BattleState>>,
-    enemy_query: Query<&BattleStats, With<Enemy>>,
+    mut enemy_query: Query<(&BattleStats, &mut CharacterAnimation), With<Enemy>>,
     player_query: Query<Entity, With<Player>>,
 ) {
     let player_ent = player_query.single();
-    let enemy_stats = enemy_query.iter().next().unwrap();
+    let (enemy_stats, mut enemy_animation) = enemy_query.iter_mut().next().unwrap();
+    enemy_animation.play(AnimationState::Attack);
     fight_event.send(FightEvent {
         target: player_ent,
         damage_amount: enemy_stats.attack,
@@ -271,29 +368,51 @@ fn despawn_menu(mut commands: Commands, button_query: Query<Entity, With<BattleM
 fn handle_accepting_reward(
     mut commands: Commands,
     ascii: Res<AsciiSpriteSheet>,
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
     mut battle_state: ResMut<State<BattleState>>,
 ) {
-    if keyboard.just_pressed(KeyCode::Space) {
+    if actions.just_pressed(Action::Confirm) {
         battle_state.set(BattleState::Exiting).unwrap();
         create_fadeout(&mut commands, None, &ascii);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn give_reward(
     mut commands: Commands,
     ascii: Res<AsciiSpriteSheet>,
     mut player_query: Query<(&mut Player, &mut BattleStats)>,
     enemy_query: Query<&Enemy>,
-    mut keyboard: ResMut<Input<KeyCode>>,
+    mut actions: ResMut<Input<Action>>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    mut play_stats: ResMut<PlayStats>,
+    damage_taken: Res<BattleDamageTaken>,
+    mut achievement_events: EventWriter<AchievementEvent>,
+    hero: Res<Hero>,
+    classes: Res<Classes>,
+    job: Res<Job>,
+    bonuses: Res<SkillBonuses>,
 ) {
-    keyboard.clear();
+    actions.clear();
+    let enemy_type = enemy_query.single().enemy_type;
     //TODO come based on enemies killed
-    let exp_reward = match enemy_query.single().enemy_type {
+    let base_exp: usize = match enemy_type {
         EnemyType::Bat => 10,
         EnemyType::Ghost => 30,
     };
-    let reward_text = format!("Earned: {} exp", exp_reward);
+    let exp_reward = (base_exp as f32 * (1.0 + bonuses.exp_gain)).round() as usize;
+    play_stats.exp_earned += exp_reward;
+    //a lost battle ends here too, it was already counted in damage_calculation
+    if player_query.single().1.health > 0 {
+        play_stats.battles_won += 1;
+        *play_stats.enemies_defeated.entry(enemy_type).or_default() += 1;
+        achievement_events.send(AchievementEvent::EnemyDefeated {
+            enemy_type,
+            damage_taken: damage_taken.0,
+        });
+    }
+    let reward_text = format!("{} earned {} exp", hero.name, exp_reward);
     let text = spawn_ascii_text(
         &mut commands,
         &ascii,
@@ -302,15 +421,29 @@ fn give_reward(
     );
     commands.entity(text).insert(BattleText);
     let (mut player, mut stats) = player_query.single_mut();
-    if player.give_exp(exp_reward, &mut stats) {
-        let level_text = "Level up!";
+    let class = classes.get(&job.class);
+    let mut lines = Vec::new();
+    if player.give_exp(exp_reward, &mut stats, &class) {
+        achievement_events.send(AchievementEvent::LevelReached(player.level));
+        lines.push(format!("{} reached level {}!", hero.name, player.level));
+        for skill in class.skills_at(player.level) {
+            lines.push(format!("Learned {}!", skill.name));
+        }
+        lines.push(format!("Skill points: {}", player.skill_points));
+    }
+    lines.push(format!(
+        "Press {}",
+        bindings.prompt(Action::Confirm, *device)
+    ));
+
+    for (i, line) in lines.iter().enumerate() {
         let text = spawn_ascii_text(
             &mut commands,
             &ascii,
-            level_text,
+            line,
             Vec3::new(
-                -((level_text.len() / 2) as f32 * TILE_SIZE),
-                -1.5 * TILE_SIZE,
+                -((line.len() / 2) as f32 * TILE_SIZE),
+                -1.5 * (i + 1) as f32 * TILE_SIZE,
                 0.0,
             ),
         );
@@ -318,25 +451,56 @@ fn give_reward(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn damage_calculation(
     mut commands: Commands,
     ascii: Res<AsciiSpriteSheet>,
     mut fight_event: EventReader<FightEvent>,
     text_query: Query<&Transform, With<BattleText>>,
-    mut target_query: Query<(&Children, &mut BattleStats)>,
+    mut target_query: Query<(
+        &Children,
+        &mut BattleStats,
+        Option<&mut CharacterAnimation>,
+        Option<&Player>,
+    )>,
     mut battle_state: ResMut<State<BattleState>>,
+    mut play_stats: ResMut<PlayStats>,
+    mut damage_taken: ResMut<BattleDamageTaken>,
+    equipped: Res<Equipped>,
 ) {
     if let Some(fight_event) = fight_event.iter().next() {
         //Get target stats and children
-        let (target_children, mut stats) = target_query
+        let (target_children, mut stats, animation, player) = target_query
             .get_mut(fight_event.target)
             .expect("Fighting enemy without stats");
 
-        //Damage calc
-        stats.health = std::cmp::max(
-            stats.health - (fight_event.damage_amount - stats.defense),
-            0,
-        );
+        let health_before = stats.health;
+        if fight_event.damage_amount < 0 {
+            stats.health = (stats.health - fight_event.damage_amount).min(stats.max_health);
+        } else {
+            if let Some(mut animation) = animation {
+                animation.play(AnimationState::Hurt);
+            }
+            //Damage calc, the gear of the player adds to its defense
+            //a hit weaker than the defense does nothing instead of healing
+            let gear_defense = if player.is_some() {
+                equipped.defense
+            } else {
+                0
+            };
+            let blocked = stats.defense + gear_defense;
+            stats.health = std::cmp::max(
+                stats.health - (fight_event.damage_amount - blocked).max(0),
+                0,
+            );
+        }
+        let damage = (health_before - stats.health).max(0) as usize;
+        if player.is_some() {
+            play_stats.damage_taken += damage;
+            damage_taken.0 += damage;
+        } else {
+            play_stats.damage_dealt += damage;
+        }
 
         //Update health
         for child in target_children.iter() {
@@ -357,6 +521,10 @@ fn damage_calculation(
             }
         }
 
+        if stats.health == 0 && player.is_some() {
+            play_stats.battles_lost += 1;
+        }
+
         //Kill enemy if dead
         //TODO support multiple enemies
         if stats.health == 0 {
@@ -367,48 +535,82 @@ fn damage_calculation(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn battle_input(
     mut commands: Commands,
-    keyboard: Res<Input<KeyCode>>,
-    player_query: Query<&BattleStats, With<Player>>,
+    actions: Res<Input<Action>>,
+    mut player_query: Query<(&BattleStats, &mut CharacterAnimation), With<Player>>,
     enemy_query: Query<Entity, With<Enemy>>,
     mut fight_event: EventWriter<FightEvent>,
     mut menu_state: ResMut<BattleMenuSelection>,
     mut battle_state: ResMut<State<BattleState>>,
     ascii: Res<AsciiSpriteSheet>,
+    mut play_stats: ResMut<PlayStats>,
+    mut achievement_events: EventWriter<AchievementEvent>,
+    button_query: Query<(Entity, &BattleMenuOption)>,
+    bonuses: Res<SkillBonuses>,
+    equipped: Res<Equipped>,
 ) {
     if battle_state.current() != &BattleState::PlayerTurn {
         return;
     }
 
-    let player_battle = player_query.single();
+    let (player_battle, mut player_animation) = player_query.single_mut();
+    let attack = player_battle.attack + equipped.attack;
 
     //TODO handle multiple enemies
     let enemy = enemy_query.single();
-    let mut new_selection = menu_state.selected as isize;
-    if keyboard.just_pressed(KeyCode::A) {
+    let options: Vec<BattleMenuOption> = MENU_ORDER
+        .into_iter()
+        .filter(|option| button_query.iter().any(|(_, button)| button == option))
+        .collect();
+    if options.is_empty() {
+        return;
+    }
+    let mut new_selection = options
+        .iter()
+        .position(|option| *option == menu_state.selected)
+        .unwrap_or(0) as isize;
+    if actions.just_pressed(Action::MoveLeft) {
         new_selection -= 1;
     }
-    if keyboard.just_pressed(KeyCode::D) {
+    if actions.just_pressed(Action::MoveRight) {
         new_selection += 1;
     }
-    new_selection = (new_selection + NUM_MENU_OPTIONS) % NUM_MENU_OPTIONS;
-
-    menu_state.selected = match new_selection {
-        0 => BattleMenuOption::Fight,
-        1 => BattleMenuOption::Run,
-        _ => unreachable!("Bad menu selection"),
-    };
+    new_selection = new_selection.rem_euclid(options.len() as isize);
+    menu_state.selected = options[new_selection as usize];
 
-    if keyboard.just_pressed(KeyCode::Space) {
+    if actions.just_pressed(Action::Confirm) {
         match menu_state.selected {
-            BattleMenuOption::Fight => fight_event.send(FightEvent {
-                //TODO select enemy and attack type
-                target: enemy,
-                damage_amount: player_battle.attack,
-                next_state: BattleState::PlayerAttack,
-            }),
+            BattleMenuOption::Fight => {
+                player_animation.play(AnimationState::Attack);
+                fight_event.send(FightEvent {
+                    //TODO select enemy and attack type
+                    target: enemy,
+                    damage_amount: bonuses.roll_damage(attack),
+                    next_state: BattleState::PlayerAttack,
+                })
+            }
+            BattleMenuOption::Skill => {
+                let power = bonuses.ability.as_ref().map_or(0, |ability| ability.power);
+                player_animation.play(AnimationState::Attack);
+                fight_event.send(FightEvent {
+                    target: enemy,
+                    damage_amount: bonuses.roll_damage(attack + power),
+                    next_state: BattleState::PlayerAttack,
+                });
+                for (button, option) in button_query.iter() {
+                    if *option == BattleMenuOption::Skill {
+                        commands.entity(button).despawn_recursive();
+                    }
+                }
+                menu_state.selected = BattleMenuOption::Fight;
+            }
+            //picked and used in class_skill_input
+            BattleMenuOption::ClassSkill => {}
             BattleMenuOption::Run => {
+                play_stats.battles_fled += 1;
+                achievement_events.send(AchievementEvent::Fled);
                 create_fadeout(&mut commands, None, &ascii);
                 battle_state.set(BattleState::Exiting).unwrap()
             }
@@ -416,6 +618,86 @@ fn battle_input(
     }
 }
 
+//up and down pick a class skill while its button is selected, confirm uses it
+#[allow(clippy::too_many_arguments)]
+fn class_skill_input(
+    mut commands: Commands,
+    actions: Res<Input<Action>>,
+    mut player_query: Query<(Entity, &BattleStats, &mut CharacterAnimation), With<Player>>,
+    enemy_query: Query<Entity, With<Enemy>>,
+    mut fight_event: EventWriter<FightEvent>,
+    mut menu_state: ResMut<BattleMenuSelection>,
+    mut class_skills: ResMut<ClassSkillsLeft>,
+    battle_state: Res<State<BattleState>>,
+    button_query: Query<(Entity, &BattleMenuOption, &Children)>,
+    label_query: Query<&Transform, With<AsciiText>>,
+    ascii: Res<AsciiSpriteSheet>,
+    bonuses: Res<SkillBonuses>,
+    equipped: Res<Equipped>,
+) {
+    if battle_state.current() != &BattleState::PlayerTurn
+        || menu_state.selected != BattleMenuOption::ClassSkill
+        || class_skills.skills.is_empty()
+    {
+        return;
+    }
+
+    let count = class_skills.skills.len();
+    let mut picked = class_skills.picked;
+    if actions.just_pressed(Action::MoveUp) {
+        picked = (picked + count - 1) % count;
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        picked = (picked + 1) % count;
+    }
+
+    if actions.just_pressed(Action::Confirm) {
+        let skill = class_skills.skills.remove(picked);
+        let (player, stats, mut animation) = player_query.single_mut();
+        if skill.heal > 0 {
+            fight_event.send(FightEvent {
+                target: player,
+                damage_amount: -skill.heal,
+                next_state: BattleState::EnemyTurn(false),
+            });
+        } else {
+            animation.play(AnimationState::Attack);
+            fight_event.send(FightEvent {
+                target: enemy_query.single(),
+                damage_amount: bonuses.roll_damage(stats.attack + equipped.attack + skill.power),
+                next_state: BattleState::PlayerAttack,
+            });
+        }
+        picked = picked.min(class_skills.skills.len().saturating_sub(1));
+    }
+    if picked == class_skills.picked && count == class_skills.skills.len() {
+        return;
+    }
+    class_skills.picked = picked;
+
+    //the button is sized for the longest name, only its label changes
+    for (button, option, children) in button_query.iter() {
+        if *option != BattleMenuOption::ClassSkill {
+            continue;
+        }
+        let skill = match class_skills.skills.get(picked) {
+            Some(skill) => skill,
+            None => {
+                commands.entity(button).despawn_recursive();
+                menu_state.selected = BattleMenuOption::Fight;
+                continue;
+            }
+        };
+        for child in children.iter() {
+            if let Ok(label) = label_query.get(*child) {
+                commands.entity(*child).despawn_recursive();
+                let text = spawn_ascii_text(&mut commands, &ascii, &skill.name, label.translation);
+                commands.entity(button).add_child(text);
+            }
+        }
+    }
+}
+
 fn battle_camera(
     mut camera_query: Query<&mut Transform, With<MainCamera>>,
     attack_fx: Res<AttackEffects>,
@@ -425,15 +707,25 @@ fn battle_camera(
     camera_transform.translation.y = 0.0;
 }
 
+//picks from the enemies of the map, weighted, anything can show up on a map without a list
+fn pick_enemy(table: &EnemyTable) -> EnemyType {
+    match WeightedIndex::new(table.0.iter().map(|(_, weight)| *weight)) {
+        Ok(weights) => table.0[weights.sample(&mut rand::thread_rng())].0,
+        //the table is empty or every weight is 0
+        Err(_) => match rand::random::<f32>() {
+            x if x < 0.5 => EnemyType::Bat,
+            _ => EnemyType::Ghost,
+        },
+    }
+}
+
 fn spawn_enemy(
     mut commands: Commands,
     ascii: Res<AsciiSpriteSheet>,
     characters: Res<CharacterSheet>,
+    enemy_table: Res<EnemyTable>,
 ) {
-    let enemy_type = match rand::random::<f32>() {
-        x if x < 0.5 => EnemyType::Bat,
-        _ => EnemyType::Ghost,
-    };
+    let enemy_type = pick_enemy(&enemy_table);
     let stats = match enemy_type {
         EnemyType::Bat => BattleStats {
             health: 3,
@@ -0,0 +1,42 @@
+//prints a dungeon floor in the map format, to look at a seed or to start a hand made map from it
+//
+//  cargo run --bin dungeon_gen -- SEED [WIDTH HEIGHT] [FLOOR] > assets/floor.map.txt
+//
+//the game builds the same floor from the map name printed on the first line of stderr
+use std::process::ExitCode;
+
+use noob_bevy::dungeon::DungeonFloor;
+
+fn main() -> ExitCode {
+    let args: Vec<u64> = match std::env::args().skip(1).map(|arg| arg.parse()).collect() {
+        Ok(args) => args,
+        Err(_) => {
+            eprintln!("usage: dungeon_gen SEED [WIDTH HEIGHT] [FLOOR]");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut floor = match args.as_slice() {
+        [seed, ..] => DungeonFloor::new(*seed, 48, 32),
+        [] => {
+            eprintln!("usage: dungeon_gen SEED [WIDTH HEIGHT] [FLOOR]");
+            return ExitCode::from(2);
+        }
+    };
+    if let [_, width, height, ..] = args.as_slice() {
+        floor = DungeonFloor::new(floor.seed, *width as i32, *height as i32);
+    }
+    if let [_, _, _, number] = args.as_slice() {
+        floor.floor = (*number as u32).max(1);
+    }
+
+    eprintln!("{}", floor.name());
+    print!("{}", floor.map_text());
+    match floor.generate() {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("the floor does not load: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
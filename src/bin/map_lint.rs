@@ -0,0 +1,419 @@
+//checks map files before the game ever loads them, for CI and for whoever is editing a map
+//
+//  cargo run --bin map_lint -- [--assets DIR] [MAP...]
+//
+//without maps every map in the assets folder is checked, warps are looked up in the assets folder like the game does
+//and warps to dungeon floors generate the floor
+//prints one line per problem and exits with 1 if there was any
+use bevy::prelude::IVec2;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use noob_bevy::{
+    dungeon::DungeonFloor,
+    tiled::read_tiled,
+    tilemap::{read_map, unknown_glyphs, validate_map, LayerKind, MapAsset, TileDef, TileTrigger},
+};
+
+//the same ones the asset loaders take, other text and JSON files in the assets folder are left alone
+const TEXT_MAP: &str = ".map.txt";
+const TILED_MAP: &str = ".tmj";
+
+fn main() -> ExitCode {
+    let mut assets = PathBuf::from("assets");
+    let mut maps = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--assets" => match args.next() {
+                Some(dir) => assets = PathBuf::from(dir),
+                None => {
+                    eprintln!("--assets needs a folder");
+                    return ExitCode::from(2);
+                }
+            },
+            _ => maps.push(PathBuf::from(arg)),
+        }
+    }
+
+    if maps.is_empty() {
+        maps = match maps_in(&assets) {
+            Ok(maps) => maps,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", assets.display(), err);
+                return ExitCode::from(2);
+            }
+        };
+    }
+
+    let mut count = 0;
+    for path in maps.iter() {
+        for problem in lint(path, &assets) {
+            println!("{}: {}", path.display(), problem);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        println!("{} maps ok", maps.len());
+        ExitCode::SUCCESS
+    } else {
+        println!("{} problems in {} maps", count, maps.len());
+        ExitCode::FAILURE
+    }
+}
+
+fn is_map(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(TEXT_MAP) || name.ends_with(TILED_MAP)
+}
+
+fn maps_in(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut maps: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_map(path))
+        .collect();
+    maps.sort();
+    Ok(maps)
+}
+
+//reads the file with the parser the game picks for its extension, without the checks of the loader
+fn read(path: &Path) -> Result<MapAsset, String> {
+    if !is_map(path) {
+        return Err(format!(
+            "not a map file, maps end in {} or {}",
+            TEXT_MAP, TILED_MAP
+        ));
+    }
+    let bytes = fs::read(path).map_err(|err| err.to_string())?;
+    if path.to_string_lossy().ends_with(TILED_MAP) {
+        read_tiled(&bytes)
+    } else {
+        read_map(&String::from_utf8_lossy(&bytes))
+    }
+}
+
+fn lint(path: &Path, assets: &Path) -> Vec<String> {
+    let map = match read(path) {
+        Ok(map) => map,
+        Err(err) => return vec![err],
+    };
+
+    let mut problems = unknown_glyphs(&map);
+    if let Err(err) = validate_map(&map) {
+        problems.push(err);
+    }
+    problems.extend(ragged_rows(&map));
+    problems.extend(reachability(&map));
+    problems.extend(enemyless_encounters(&map));
+    problems.extend(broken_warps(&map, assets));
+    problems
+}
+
+//every row of the ground is as wide as the first one, other layers never reach past the ground
+fn ragged_rows(map: &MapAsset) -> Vec<String> {
+    let mut problems = Vec::new();
+    let ground = match map
+        .layers
+        .iter()
+        .find(|layer| layer.kind == LayerKind::Ground)
+    {
+        Some(ground) => ground,
+        None => return problems,
+    };
+    let width = ground.rows.first().map_or(0, |row| row.chars().count());
+
+    for layer in map.layers.iter() {
+        for (y, row) in layer.rows.iter().enumerate() {
+            let length = row.chars().count();
+            let line = layer.first_line + y;
+            if layer.kind == LayerKind::Ground && length != width {
+                problems.push(format!(
+                    "line {} is {} tiles wide, the first row is {}",
+                    line, length, width
+                ));
+            } else if layer.kind != LayerKind::Ground && (length > width || y >= ground.rows.len())
+            {
+                problems.push(format!(
+                    "line {} of the {:?} layer reaches past the ground",
+                    line, layer.kind
+                ));
+            }
+        }
+    }
+    problems
+}
+
+enum Walk {
+    Outside,
+    Blocked,
+    Open,
+    //stepping on it takes the player away, so it may sit on the edge
+    Warp,
+}
+
+//doors count as open, the player can always open them
+fn walk(map: &MapAsset, tile: IVec2) -> Walk {
+    let mut defs = map.defs_at(tile.x, tile.y).peekable();
+    if defs.peek().is_none() {
+        return Walk::Outside;
+    }
+    let mut warp = false;
+    for (_, def) in defs {
+        let def = match def {
+            Some(def) => def,
+            //unknown glyphs are reported on their own
+            None => return Walk::Blocked,
+        };
+        let door = matches!(def.trigger, Some(TileTrigger::Door));
+        if (def.collider && !door) || def.npc.is_some() {
+            return Walk::Blocked;
+        }
+        warp |= matches!(def.trigger, Some(TileTrigger::Warp { .. }));
+    }
+    if warp {
+        Walk::Warp
+    } else {
+        Walk::Open
+    }
+}
+
+const NEIGHBOURS: [IVec2; 4] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+];
+
+//floods the walkable tiles from the tile, warps are reached but never walked through
+fn flood(map: &MapAsset, start: IVec2) -> (BTreeSet<(i32, i32)>, BTreeSet<(i32, i32)>) {
+    let mut reached = BTreeSet::new();
+    let mut open_edges = BTreeSet::new();
+    let mut queue = VecDeque::from([start]);
+    reached.insert((start.x, start.y));
+
+    while let Some(tile) = queue.pop_front() {
+        if matches!(walk(map, tile), Walk::Warp) {
+            continue;
+        }
+        for offset in NEIGHBOURS {
+            let next = tile + offset;
+            match walk(map, next) {
+                Walk::Outside => {
+                    open_edges.insert((tile.x, tile.y));
+                }
+                Walk::Blocked => {}
+                Walk::Open | Walk::Warp => {
+                    if reached.insert((next.x, next.y)) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+    }
+    (reached, open_edges)
+}
+
+//the player can not walk off the map, and every walkable tile can be reached from the spawn point
+fn reachability(map: &MapAsset) -> Vec<String> {
+    let mut problems = Vec::new();
+    let spawn = match map.metadata.spawn {
+        Some((x, y)) => IVec2::new(x, y),
+        //validate_map already complains
+        None => return problems,
+    };
+    if !matches!(walk(map, spawn), Walk::Open | Walk::Warp) {
+        return problems;
+    }
+
+    let (mut reached, open_edges) = flood(map, spawn);
+    for (x, y) in open_edges {
+        problems.push(format!(
+            "open border at ({}, {}), the player can walk out of the map",
+            x, y
+        ));
+    }
+
+    let height = map
+        .layers
+        .iter()
+        .map(|layer| layer.rows.len())
+        .max()
+        .unwrap_or(0);
+    let width = map
+        .layers
+        .iter()
+        .flat_map(|layer| layer.rows.iter())
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let tile = IVec2::new(x, y);
+            if reached.contains(&(x, y)) || !matches!(walk(map, tile), Walk::Open | Walk::Warp) {
+                continue;
+            }
+            //the whole area is reported once, by its first tile
+            let (area, _) = flood(map, tile);
+            problems.push(format!(
+                "area of {} tiles at ({}, {}) can not be reached from the spawn point",
+                area.len(),
+                x,
+                y
+            ));
+            reached.extend(area);
+        }
+    }
+    problems
+}
+
+fn enemyless_encounters(map: &MapAsset) -> Vec<String> {
+    if !map.metadata.enemies.is_empty() {
+        return Vec::new();
+    }
+    used_defs(map)
+        .filter(|(_, def)| def.encounter.is_some())
+        .map(|(glyph, _)| {
+            format!(
+                "{:?} has encounters but the map has no enemies, add enemies: [(Bat, 1)] to the header",
+                glyph
+            )
+        })
+        .collect()
+}
+
+//the map has to exist in the assets folder, or be a dungeon floor, and have the entry point
+fn broken_warps(map: &MapAsset, assets: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (glyph, def) in used_defs(map) {
+        let (target, entry) = match &def.trigger {
+            Some(TileTrigger::Warp { map, entry }) => (map, entry),
+            _ => continue,
+        };
+        let path = assets.join(target);
+        let target_map = match DungeonFloor::from_name(target) {
+            Some(floor) => floor.generate(),
+            None if !path.exists() => {
+                problems.push(format!(
+                    "{:?} warps to {}, which does not exist",
+                    glyph, target
+                ));
+                continue;
+            }
+            None => read(&path),
+        };
+        match target_map {
+            Ok(target_map) if !target_map.metadata.entries.contains_key(entry) => {
+                problems.push(format!(
+                    "{:?} warps to entry point {} of {}, which has no such entry",
+                    glyph, entry, target
+                ))
+            }
+            Ok(_) => {}
+            Err(err) => problems.push(format!(
+                "{:?} warps to {}, which does not load: {}",
+                glyph, target, err
+            )),
+        }
+    }
+    problems
+}
+
+//legend entries that show up somewhere in the map, in legend order
+fn used_defs(map: &MapAsset) -> impl Iterator<Item = (char, &TileDef)> {
+    let used: BTreeSet<char> = map
+        .layers
+        .iter()
+        .flat_map(|layer| layer.rows.iter())
+        .flat_map(|row| row.chars())
+        .collect();
+    map.metadata
+        .legend
+        .iter()
+        .filter(move |(glyph, _)| used.contains(glyph))
+        .map(|(glyph, def)| (*glyph, def))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = r#"(
+    legend: {
+        '#': (collider: true),
+        '.': (),
+        '>': (trigger: Some(Warp(map: "cave.map.txt", entry: "stairs"))),
+        'd': (trigger: Some(Warp(map: "dungeon:1:1:48x32", entry: "up"))),
+        'm': (trigger: Some(Warp(map: "missing.map.txt", entry: "stairs"))),
+        'e': (trigger: Some(Warp(map: "cave.map.txt", entry: "nowhere"))),
+    },
+    spawn: (1, 1),
+)
+---
+"#;
+
+    fn map(rows: &str) -> MapAsset {
+        read_map(&format!("{}{}", HEADER, rows)).expect("test map reads")
+    }
+
+    fn assets() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")
+    }
+
+    #[test]
+    fn closed_map_has_no_problems() {
+        let map = map("#####\n#..>#\n#####\n");
+        assert!(ragged_rows(&map).is_empty());
+        assert!(reachability(&map).is_empty());
+        assert!(broken_warps(&map, &assets()).is_empty());
+    }
+
+    #[test]
+    fn ragged_row_is_reported_by_line() {
+        let map = map("#####\n#..#\n#####\n");
+        let line = HEADER.lines().count() + 2;
+        assert_eq!(
+            ragged_rows(&map),
+            vec![format!("line {} is 4 tiles wide, the first row is 5", line)]
+        );
+    }
+
+    #[test]
+    fn open_border_is_reported() {
+        let map = map("#####\n#....\n#####\n");
+        assert_eq!(
+            reachability(&map),
+            vec!["open border at (4, 1), the player can walk out of the map".to_string()]
+        );
+    }
+
+    #[test]
+    fn unreachable_area_is_reported_once() {
+        let map = map("#######\n#..#..#\n#######\n");
+        assert_eq!(
+            reachability(&map),
+            vec!["area of 2 tiles at (4, 1) can not be reached from the spawn point".to_string()]
+        );
+    }
+
+    #[test]
+    fn warps_stop_the_flood() {
+        //the tile behind the stairs is only reachable by taking them
+        let map = map("######\n#..>.#\n######\n");
+        assert_eq!(reachability(&map).len(), 1);
+    }
+
+    #[test]
+    fn broken_warps_are_reported() {
+        let map = map("######\n#.dme#\n######\n");
+        let problems = broken_warps(&map, &assets());
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        //in legend order
+        assert!(problems[0].contains("entry point nowhere of cave.map.txt"));
+        assert!(problems[1].contains("missing.map.txt, which does not exist"));
+    }
+}
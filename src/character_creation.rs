@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet},
+    classes::{Classes, Job},
+    fadeout::create_fadeout,
+    graphics::{CharacterSheet, FrameAnimation, CHARACTER_APPEARANCES},
+    input::{Action, InputDevice, KeyBindings},
+    GameState, TILE_SIZE,
+};
+
+pub struct CharacterCreationPlugin;
+
+//who the player is, picked once when starting a new game and kept in the save
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Hero {
+    pub name: String,
+    //which character of characters.png the hero looks like
+    pub appearance: usize,
+}
+
+impl Default for Hero {
+    fn default() -> Self {
+        Hero {
+            name: "Hero".to_string(),
+            appearance: 1,
+        }
+    }
+}
+
+//letters are picked from a grid so the name can be entered with a gamepad too
+const LETTER_ROWS: [&str; 5] = [
+    "ABCDEFGHIJKLM",
+    "NOPQRSTUVWXYZ",
+    "abcdefghijklm",
+    "nopqrstuvwxyz",
+    "0123456789-'.",
+];
+const LOOK_ROW: usize = LETTER_ROWS.len();
+const CLASS_ROW: usize = LETTER_ROWS.len() + 1;
+const DONE_ROW: usize = LETTER_ROWS.len() + 2;
+const MAX_NAME_LENGTH: usize = 8;
+
+#[derive(Default)]
+struct CreationCursor {
+    name: String,
+    appearance: usize,
+    //index into the loaded classes
+    class: usize,
+    row: usize,
+    column: usize,
+    //set once done is picked, the fade takes a moment and input is ignored meanwhile
+    finished: bool,
+}
+
+#[derive(Component)]
+struct CreationText;
+
+#[derive(Component)]
+struct HeroPreview;
+
+impl Plugin for CharacterCreationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Hero>()
+            .init_resource::<CreationCursor>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::CharacterCreation)
+                    .with_system(start_character_creation),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::CharacterCreation)
+                    .with_system(character_creation_input.label("creation_input"))
+                    .with_system(draw_character_creation.after("creation_input"))
+                    .with_system(update_hero_preview.after("creation_input")),
+            )
+            .add_system_set(
+                SystemSet::on_pause(GameState::CharacterCreation)
+                    .with_system(despawn_character_creation),
+            );
+    }
+}
+
+fn start_character_creation(
+    mut commands: Commands,
+    mut cursor: ResMut<CreationCursor>,
+    hero: Res<Hero>,
+    job: Res<Job>,
+    classes: Res<Classes>,
+    characters: Res<CharacterSheet>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    //the confirm that pressed start game is still fresh this frame and would add a letter
+    actions.clear();
+    *cursor = CreationCursor {
+        name: hero.name.clone(),
+        appearance: hero.appearance,
+        class: classes
+            .0
+            .iter()
+            .position(|class| class.id == job.class)
+            .unwrap_or(0),
+        ..Default::default()
+    };
+
+    let mut sprite = TextureAtlasSprite::new(characters.player_down[1]);
+    sprite.custom_size = Some(Vec2::splat(TILE_SIZE * 3.0));
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: sprite,
+            texture_atlas: characters.handle.clone(),
+            transform: Transform::from_xyz(0.4, -0.3, 900.0),
+            ..Default::default()
+        })
+        .insert(FrameAnimation::new(characters.player_down.to_vec(), 0.2))
+        .insert(HeroPreview)
+        .insert(Name::new("Hero Preview"));
+}
+
+fn character_creation_input(
+    mut commands: Commands,
+    mut cursor: ResMut<CreationCursor>,
+    mut hero: ResMut<Hero>,
+    mut characters: ResMut<CharacterSheet>,
+    mut job: ResMut<Job>,
+    classes: Res<Classes>,
+    actions: Res<Input<Action>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    if cursor.finished {
+        return;
+    }
+
+    let rows = DONE_ROW as isize + 1;
+    if actions.just_pressed(Action::MoveUp) {
+        cursor.row = (cursor.row as isize - 1).rem_euclid(rows) as usize;
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        cursor.row = (cursor.row as isize + 1).rem_euclid(rows) as usize;
+    }
+
+    let mut step = 0;
+    if actions.just_pressed(Action::MoveLeft) {
+        step -= 1;
+    }
+    if actions.just_pressed(Action::MoveRight) {
+        step += 1;
+    }
+    if step != 0 {
+        //left and right walk along a row of letters, or flip through the looks and classes
+        if cursor.row == LOOK_ROW {
+            let looks = CHARACTER_APPEARANCES as isize;
+            cursor.appearance = (cursor.appearance as isize + step).rem_euclid(looks) as usize;
+        } else if cursor.row == CLASS_ROW {
+            let count = classes.0.len() as isize;
+            cursor.class = (cursor.class as isize + step).rem_euclid(count) as usize;
+        } else if cursor.row < LOOK_ROW {
+            let columns = LETTER_ROWS[cursor.row].len() as isize;
+            cursor.column = (cursor.column as isize + step).rem_euclid(columns) as usize;
+        }
+    }
+
+    if actions.just_pressed(Action::Cancel) {
+        cursor.name.pop();
+    }
+
+    if actions.just_pressed(Action::Confirm) {
+        if cursor.row < LOOK_ROW {
+            if cursor.name.len() < MAX_NAME_LENGTH {
+                let letter = LETTER_ROWS[cursor.row].chars().nth(cursor.column).unwrap();
+                cursor.name.push(letter);
+            }
+        } else if cursor.row == DONE_ROW && !cursor.name.is_empty() {
+            hero.name = cursor.name.clone();
+            hero.appearance = cursor.appearance;
+            characters.select_player(hero.appearance);
+            job.class = classes.0[cursor.class].id.clone();
+            cursor.finished = true;
+            create_fadeout(&mut commands, Some(GameState::Overworld), &ascii);
+        }
+    }
+}
+
+fn draw_character_creation(
+    mut commands: Commands,
+    cursor: Res<CreationCursor>,
+    classes: Res<Classes>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<CreationText>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    if !cursor.is_changed() && !device.is_changed() {
+        return;
+    }
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let marker = |row: usize| if cursor.row == row { '>' } else { ' ' };
+    let mut name = cursor.name.clone();
+    name.push_str(&"_".repeat(MAX_NAME_LENGTH - cursor.name.len()));
+
+    let mut lines = vec![
+        "Create your hero".to_string(),
+        String::new(),
+        format!("Name: {}", name),
+        String::new(),
+    ];
+    for (row, letters) in LETTER_ROWS.iter().enumerate() {
+        let line: String = letters
+            .chars()
+            .enumerate()
+            .flat_map(|(column, letter)| {
+                let selected = cursor.row == row && cursor.column == column;
+                [if selected { '>' } else { ' ' }, letter]
+            })
+            .collect();
+        lines.push(line);
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "{}Look  < {}/{} >",
+        marker(LOOK_ROW),
+        cursor.appearance + 1,
+        CHARACTER_APPEARANCES
+    ));
+    lines.push(format!(
+        "{}Class < {} >",
+        marker(CLASS_ROW),
+        classes.0[cursor.class].id
+    ));
+    lines.push(String::new());
+    lines.push(format!("{}Done", marker(DONE_ROW)));
+    lines.push(String::new());
+    lines.push(format!(
+        "{} add letter {} erase",
+        bindings.prompt(Action::Confirm, *device),
+        bindings.prompt(Action::Cancel, *device)
+    ));
+
+    let left = -1.2;
+    let top = 0.8;
+    for (i, line) in lines.iter().enumerate() {
+        let text = spawn_ascii_text(
+            &mut commands,
+            &ascii,
+            line,
+            Vec3::new(left, top - i as f32 * TILE_SIZE, 900.0),
+        );
+        commands.entity(text).insert(CreationText);
+    }
+}
+
+fn update_hero_preview(
+    cursor: Res<CreationCursor>,
+    mut preview_query: Query<(&mut FrameAnimation, &mut TextureAtlasSprite), With<HeroPreview>>,
+    characters: Res<CharacterSheet>,
+) {
+    if !cursor.is_changed() {
+        return;
+    }
+    let frames = characters.player_frames(cursor.appearance)[0];
+    for (mut animation, mut sprite) in preview_query.iter_mut() {
+        if animation.frames != frames {
+            animation.frames = frames.to_vec();
+            animation.current_frame = 0;
+            sprite.index = frames[0];
+        }
+    }
+}
+
+fn despawn_character_creation(
+    mut commands: Commands,
+    text_query: Query<Entity, Or<(With<CreationText>, With<HeroPreview>)>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,339 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet},
+    battle::BattleStats,
+    equipment::{Armory, EquipmentDef, Equipped},
+    input::{Action, InputDevice, KeyBindings},
+    inventory::Inventory,
+    player::Player,
+    save::AutosaveEvent,
+    GameState, MainCamera, RESOLUTION, TILE_SIZE,
+};
+
+pub struct ClassesPlugin;
+
+//what a level up adds on top of the starting stats, health raises the maximum too
+#[derive(Clone, Copy, Deserialize)]
+pub struct StatGrowth {
+    pub health: isize,
+    pub attack: isize,
+    pub defense: isize,
+}
+
+//usable once per battle, an attack that adds its power to the damage or a heal
+#[derive(Clone, Deserialize)]
+pub struct LearnableSkill {
+    pub level: usize,
+    pub name: String,
+    #[serde(default)]
+    pub power: isize,
+    #[serde(default)]
+    pub heal: isize,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ClassDef {
+    pub id: String,
+    pub starting: BattleStats,
+    //growth for every level up in order, the last entry repeats for higher levels
+    pub growth: Vec<StatGrowth>,
+    pub skills: Vec<LearnableSkill>,
+    //kinds of equipment the class is allowed to use, see assets/equipment.ron
+    #[serde(default)]
+    pub equipment: Vec<String>,
+}
+
+impl Default for ClassDef {
+    //what every player was before classes existed, used when the class data is missing
+    fn default() -> Self {
+        ClassDef {
+            id: "Adventurer".to_string(),
+            starting: BattleStats {
+                health: 10,
+                max_health: 10,
+                attack: 2,
+                defense: 1,
+            },
+            growth: vec![StatGrowth {
+                health: 2,
+                attack: 1,
+                defense: 1,
+            }],
+            skills: Vec::new(),
+            equipment: Vec::new(),
+        }
+    }
+}
+
+impl ClassDef {
+    //growth applied when going from level to level + 1
+    pub fn growth_at(&self, level: usize) -> StatGrowth {
+        self.growth
+            .get(level.saturating_sub(1))
+            .or_else(|| self.growth.last())
+            .copied()
+            .unwrap_or(StatGrowth {
+                health: 0,
+                attack: 0,
+                defense: 0,
+            })
+    }
+
+    //full health stats of the class at the given level
+    pub fn stats_at(&self, level: usize) -> BattleStats {
+        let mut stats = self.starting.clone();
+        for level in 1..level {
+            let growth = self.growth_at(level);
+            stats.max_health += growth.health;
+            stats.attack += growth.attack;
+            stats.defense += growth.defense;
+        }
+        stats.health = stats.max_health;
+        stats
+    }
+
+    pub fn can_equip(&self, gear: &EquipmentDef) -> bool {
+        self.equipment.contains(&gear.kind)
+    }
+
+    pub fn skills_at(&self, level: usize) -> impl Iterator<Item = &LearnableSkill> {
+        self.skills.iter().filter(move |skill| skill.level == level)
+    }
+
+    //every skill learned up to the level
+    pub fn skills_until(&self, level: usize) -> impl Iterator<Item = &LearnableSkill> {
+        self.skills.iter().filter(move |skill| skill.level <= level)
+    }
+}
+
+//loaded from assets/classes.ron
+pub struct Classes(pub Vec<ClassDef>);
+
+impl Classes {
+    pub fn get(&self, id: &str) -> ClassDef {
+        self.0
+            .iter()
+            .find(|class| class.id == id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ClassLevel {
+    pub level: usize,
+    pub exp: usize,
+}
+
+//the player's current class, the level and exp of the player belong to it
+//every other class keeps its own level for when the player switches back
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub class: String,
+    pub levels: BTreeMap<String, ClassLevel>,
+}
+
+impl Job {
+    //the player only carries the level of the current class
+    fn level_of(&self, class: &str, player: &Player) -> usize {
+        if class == self.class {
+            player.level
+        } else {
+            self.levels.get(class).map_or(1, |progress| progress.level)
+        }
+    }
+}
+
+#[derive(Default)]
+struct JobMenu {
+    selected: usize,
+}
+
+#[derive(Component)]
+struct JobText;
+
+impl Plugin for ClassesPlugin {
+    fn build(&self, app: &mut App) {
+        let classes = load_classes();
+        let job = Job {
+            class: classes.0.first().cloned().unwrap_or_default().id,
+            levels: BTreeMap::new(),
+        };
+        app.insert_resource(classes)
+            .insert_resource(job)
+            .init_resource::<JobMenu>()
+            .add_system_set(SystemSet::on_enter(GameState::JobChange).with_system(reset_job_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::JobChange)
+                    .with_system(job_menu_input.label("job_input"))
+                    .with_system(draw_job_menu.after("job_input")),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::JobChange).with_system(despawn_job_text));
+    }
+}
+
+fn load_classes() -> Classes {
+    let path = "assets/classes.ron";
+    let classes = match fs::read_to_string(path) {
+        Ok(contents) => match ron::from_str::<Vec<ClassDef>>(&contents) {
+            Ok(classes) => classes,
+            Err(err) => {
+                warn!("Could not parse {}: {}", path, err);
+                Vec::new()
+            }
+        },
+        Err(err) => {
+            warn!("Could not read {}: {}", path, err);
+            Vec::new()
+        }
+    };
+    if classes.is_empty() {
+        return Classes(vec![ClassDef::default()]);
+    }
+    Classes(classes)
+}
+
+fn reset_job_menu(mut menu: ResMut<JobMenu>, job: Res<Job>, classes: Res<Classes>) {
+    menu.selected = classes
+        .0
+        .iter()
+        .position(|class| class.id == job.class)
+        .unwrap_or(0);
+}
+
+fn job_menu_input(
+    mut menu: ResMut<JobMenu>,
+    mut job: ResMut<Job>,
+    mut player_query: Query<(&mut Player, &mut BattleStats)>,
+    classes: Res<Classes>,
+    actions: Res<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+    mut autosave: EventWriter<AutosaveEvent>,
+) {
+    if actions.just_pressed(Action::Cancel) {
+        let _ = state.pop();
+        return;
+    }
+
+    let count = classes.0.len();
+    if actions.just_pressed(Action::MoveUp) {
+        menu.selected = (menu.selected + count - 1) % count;
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        menu.selected = (menu.selected + 1) % count;
+    }
+
+    if actions.just_pressed(Action::Confirm) {
+        let class = &classes.0[menu.selected];
+        if class.id != job.class {
+            let (mut player, mut stats) = player_query.single_mut();
+            let previous = job.class.clone();
+            job.levels.insert(
+                previous,
+                ClassLevel {
+                    level: player.level,
+                    exp: player.exp,
+                },
+            );
+            let progress = job
+                .levels
+                .get(&class.id)
+                .copied()
+                .unwrap_or(ClassLevel { level: 1, exp: 0 });
+            player.level = progress.level;
+            player.exp = progress.exp;
+            *stats = class.stats_at(progress.level);
+            job.class = class.id.clone();
+            autosave.send(AutosaveEvent);
+        }
+        let _ = state.pop();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_job_menu(
+    mut commands: Commands,
+    menu: Res<JobMenu>,
+    job: Res<Job>,
+    classes: Res<Classes>,
+    player_query: Query<&Player>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<JobText>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+    inventory: Res<Inventory>,
+    armory: Res<Armory>,
+) {
+    if !text_query.is_empty() && !menu.is_changed() && !device.is_changed() {
+        return;
+    }
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let player = player_query.single();
+    let camera = camera_query.single().translation;
+    let left = camera.x - RESOLUTION + TILE_SIZE;
+    let top = camera.y + 1.0 - TILE_SIZE;
+
+    let mut lines = vec!["Change class".to_string(), String::new()];
+    for (i, class) in classes.0.iter().enumerate() {
+        let marker = if i == menu.selected { ">" } else { " " };
+        let level = job.level_of(&class.id, player);
+        let current = if class.id == job.class { " *" } else { "" };
+        lines.push(format!(
+            "{} {:<10}Lv {}{}",
+            marker, class.id, level, current
+        ));
+    }
+
+    let class = &classes.0[menu.selected];
+    let stats = class.stats_at(job.level_of(&class.id, player));
+    let skills = class
+        .skills
+        .iter()
+        .map(|skill| format!("{} {}", skill.name, skill.level))
+        .collect::<Vec<_>>()
+        .join(", ");
+    lines.push(String::new());
+    lines.push(format!(
+        "HP {}  Atk {}  Def {}",
+        stats.max_health, stats.attack, stats.defense
+    ));
+    lines.push(format!("Skills: {}", skills));
+    lines.push(format!("Equips: {}", class.equipment.join(", ")));
+    //what the class would wear out of the gear the player carries
+    let gear = Equipped::choose(class, &armory, &inventory)
+        .gear
+        .into_values()
+        .collect::<Vec<_>>();
+    if !gear.is_empty() {
+        lines.push(format!("Wears: {}", gear.join(", ")));
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "{} change {} back",
+        bindings.prompt(Action::Confirm, *device),
+        bindings.prompt(Action::Cancel, *device)
+    ));
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = spawn_ascii_text(
+            &mut commands,
+            &ascii,
+            line,
+            Vec3::new(left, top - i as f32 * TILE_SIZE, 900.0),
+        );
+        commands.entity(text).insert(JobText);
+    }
+}
+
+fn despawn_job_text(mut commands: Commands, text_query: Query<Entity, With<JobText>>) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet},
+    input::{Action, InputDevice, KeyBindings},
+    player::Player,
+    GameState, MainCamera, RESOLUTION, TILE_SIZE,
+};
+
+pub struct ControlsMenuPlugin;
+
+#[derive(Component)]
+struct ControlsText;
+
+#[derive(Default)]
+struct ControlsMenu {
+    selected: usize,
+    //waiting for the next key press to bind it to the selected action
+    rebinding: bool,
+}
+
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ControlsMenu>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Overworld).with_system(open_controls_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::Controls).with_system(reset_controls_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Controls)
+                    .with_system(controls_menu_input.label("controls_input"))
+                    .with_system(draw_controls_menu.after("controls_input")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Controls).with_system(despawn_controls_text),
+            );
+    }
+}
+
+fn open_controls_menu(
+    player_query: Query<&Player>,
+    actions: Res<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let player = player_query.single();
+    if player.active && actions.just_pressed(Action::Menu) {
+        let _ = state.push(GameState::Controls);
+    }
+}
+
+fn reset_controls_menu(mut menu: ResMut<ControlsMenu>) {
+    *menu = ControlsMenu::default();
+}
+
+fn controls_menu_input(
+    mut menu: ResMut<ControlsMenu>,
+    mut bindings: ResMut<KeyBindings>,
+    actions: Res<Input<Action>>,
+    keyboard: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if menu.rebinding {
+        let action = Action::ALL[menu.selected];
+        //a key replaces the keyboard binding and a gamepad button the gamepad one
+        if let Some(key) = keyboard.get_just_pressed().next() {
+            bindings.bindings.insert(action, vec![*key]);
+            menu.rebinding = false;
+        } else if let Some(button) = gamepad_buttons.get_just_pressed().next() {
+            bindings.buttons.insert(action, vec![button.button_type]);
+            menu.rebinding = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::Cancel) {
+        bindings.save();
+        let _ = state.pop();
+        return;
+    }
+
+    let count = Action::ALL.len();
+    if actions.just_pressed(Action::MoveUp) {
+        menu.selected = (menu.selected + count - 1) % count;
+    }
+    if actions.just_pressed(Action::MoveDown) {
+        menu.selected = (menu.selected + 1) % count;
+    }
+    if actions.just_pressed(Action::Confirm) {
+        menu.rebinding = true;
+    }
+}
+
+fn draw_controls_menu(
+    mut commands: Commands,
+    menu: Res<ControlsMenu>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<ControlsText>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    if !menu.is_changed() && !bindings.is_changed() && !device.is_changed() {
+        return;
+    }
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let camera = camera_query.single().translation;
+    let left = camera.x - RESOLUTION + TILE_SIZE;
+    let top = camera.y + 1.0 - TILE_SIZE;
+
+    let mut lines = vec![
+        "Controls".to_string(),
+        String::new(),
+        format!("  {:<11}{:<10}{}", "", "Keyboard", "Gamepad"),
+    ];
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let marker = if i == menu.selected { ">" } else { " " };
+        let (keys, buttons) = if i == menu.selected && menu.rebinding {
+            ("press a key".to_string(), String::new())
+        } else {
+            (bindings.key_names(*action), bindings.button_names(*action))
+        };
+        let conflict = if bindings.has_conflict(*action) {
+            " !"
+        } else {
+            ""
+        };
+        let name = format!("{:?}", action);
+        lines.push(format!(
+            "{} {:<11}{:<10}{}{}",
+            marker, name, keys, buttons, conflict
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "{}/{} move {} rebind {} back",
+        bindings.prompt(Action::MoveUp, *device),
+        bindings.prompt(Action::MoveDown, *device),
+        bindings.prompt(Action::Confirm, *device),
+        bindings.prompt(Action::Cancel, *device)
+    ));
+    lines.push("! = bound to more than one action".to_string());
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = spawn_ascii_text(
+            &mut commands,
+            &ascii,
+            line,
+            Vec3::new(left, top - i as f32 * TILE_SIZE, 900.0),
+        );
+        commands.entity(text).insert(ControlsText);
+    }
+}
+
+fn despawn_controls_text(mut commands: Commands, text_query: Query<Entity, With<ControlsText>>) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,363 @@
+use std::fmt::Write;
+
+use crate::tilemap::{parse_map, MapAsset};
+
+//floors of a dungeon are not files, they are built from their map name whenever they are needed
+//"dungeon:7:1:48x32" is the first floor of the dungeon with seed 7 on a 48x32 map,
+//the same name always builds the same floor, so saves and opened chests keep working
+const PREFIX: &str = "dungeon";
+
+//where the stairs up of the first floor lead
+const EXIT_MAP: &str = "cave.map.txt";
+const EXIT_ENTRY: &str = "dungeon";
+
+const MIN_WIDTH: i32 = 16;
+const MIN_HEIGHT: i32 = 12;
+const ROOM_ATTEMPTS: usize = 200;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DungeonFloor {
+    pub seed: u64,
+    //1 is the floor right below the entrance
+    pub floor: u32,
+    pub width: i32,
+    pub height: i32,
+}
+
+//splitmix64, rand does not promise StdRng gives the same numbers in every version
+struct DungeonRng(u64);
+
+impl DungeonRng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    //from min up to max, max left out
+    fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next() % (max - min).max(1) as u64) as i32
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Room {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl Room {
+    fn center(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+
+    //with the direction pointing out of the room from each
+    fn corners(&self) -> [(i32, i32, i32, i32); 4] {
+        let (right, bottom) = (self.x + self.width - 1, self.y + self.height - 1);
+        [
+            (self.x, self.y, -1, -1),
+            (right, self.y, 1, -1),
+            (self.x, bottom, -1, 1),
+            (right, bottom, 1, 1),
+        ]
+    }
+
+    //rooms keep a wall between them
+    fn overlaps(&self, other: &Room) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+}
+
+//a corner with walls on both outer sides has no corridor going through it, so blocking it cuts nothing off
+fn walled_in(rows: &[Vec<char>], (x, y, out_x, out_y): (i32, i32, i32, i32)) -> bool {
+    rows[y as usize][(x + out_x) as usize] == '#' && rows[(y + out_y) as usize][x as usize] == '#'
+}
+
+//stairs go in a quiet corner so walking down a corridor never takes them by accident,
+//the entry point is the tile next to them, towards the middle of the room
+fn stairs_spot(
+    rows: &[Vec<char>],
+    room: &Room,
+    taken: Option<(i32, i32)>,
+) -> ((i32, i32), (i32, i32)) {
+    let free = |(x, y, _, _): &(i32, i32, i32, i32)| taken != Some((*x, *y));
+    let corners = room.corners();
+    let (x, y, out_x, _) = corners
+        .iter()
+        .copied()
+        .filter(free)
+        .find(|corner| walled_in(rows, *corner))
+        .or_else(|| corners.iter().copied().find(free))
+        .expect("a room has four corners");
+    ((x, y), (x - out_x, y))
+}
+
+impl DungeonFloor {
+    pub fn new(seed: u64, width: i32, height: i32) -> Self {
+        DungeonFloor {
+            seed,
+            floor: 1,
+            width: width.max(MIN_WIDTH),
+            height: height.max(MIN_HEIGHT),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut parts = name.split(':');
+        if parts.next()? != PREFIX {
+            return None;
+        }
+        let seed = parts.next()?.parse().ok()?;
+        let floor = parts.next()?.parse().ok().filter(|floor| *floor > 0)?;
+        let (width, height) = parts.next()?.split_once('x')?;
+        let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+        if parts.next().is_some() || width < MIN_WIDTH || height < MIN_HEIGHT {
+            return None;
+        }
+        Some(DungeonFloor {
+            seed,
+            floor,
+            width,
+            height,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "{}:{}:{}:{}x{}",
+            PREFIX, self.seed, self.floor, self.width, self.height
+        )
+    }
+
+    fn below(&self) -> Self {
+        DungeonFloor {
+            floor: self.floor + 1,
+            ..*self
+        }
+    }
+
+    fn above(&self) -> Option<Self> {
+        (self.floor > 1).then(|| DungeonFloor {
+            floor: self.floor - 1,
+            ..*self
+        })
+    }
+
+    //the floor goes through the same parser as the map files, so a generator bug shows up as a load error
+    pub fn generate(&self) -> Result<MapAsset, String> {
+        parse_map(&self.map_text())
+    }
+
+    //rooms joined by corridors, stairs up in the first room and down in the last one,
+    //some of the rooms in between get tall grass with monsters and some a chest
+    pub fn map_text(&self) -> String {
+        //every floor has its own numbers, but the same ones every time
+        let mut rng =
+            DungeonRng(self.seed ^ u64::from(self.floor).wrapping_mul(0xA24B_AED4_963E_E407));
+        let mut rows = vec![vec!['#'; self.width as usize]; self.height as usize];
+
+        let max_rooms = ((self.width * self.height) / 80).max(2) as usize;
+        let mut rooms: Vec<Room> = Vec::new();
+        for _ in 0..ROOM_ATTEMPTS {
+            if rooms.len() >= max_rooms {
+                break;
+            }
+            let width = rng.range(4, 10);
+            let height = rng.range(3, 7);
+            let room = Room {
+                x: rng.range(1, self.width - width),
+                y: rng.range(1, self.height - height),
+                width,
+                height,
+            };
+            if !rooms.iter().any(|other| other.overlaps(&room)) {
+                rooms.push(room);
+            }
+        }
+
+        for room in rooms.iter() {
+            for y in room.y..room.y + room.height {
+                for x in room.x..room.x + room.width {
+                    rows[y as usize][x as usize] = '.';
+                }
+            }
+        }
+
+        //every room is joined to the one placed before it, so all of them are connected
+        for pair in rooms.windows(2) {
+            let (from, to) = (pair[0].center(), pair[1].center());
+            let corner = if rng.chance(50) {
+                (to.0, from.1)
+            } else {
+                (from.0, to.1)
+            };
+            for (start, end) in [(from, corner), (corner, to)] {
+                for y in start.1.min(end.1)..=start.1.max(end.1) {
+                    for x in start.0.min(end.0)..=start.0.max(end.0) {
+                        rows[y as usize][x as usize] = '.';
+                    }
+                }
+            }
+        }
+
+        let mut chests = Vec::new();
+        let middle = rooms.len().saturating_sub(1).max(1);
+        for room in rooms[1..middle].iter() {
+            if rng.chance(50) {
+                let width = rng.range(2, room.width + 1);
+                let height = rng.range(2, room.height + 1);
+                let x = rng.range(room.x, room.x + room.width - width + 1);
+                let y = rng.range(room.y, room.y + room.height - height + 1);
+                for row in rows[y as usize..(y + height) as usize].iter_mut() {
+                    for tile in row[x as usize..(x + width) as usize].iter_mut() {
+                        *tile = '"';
+                    }
+                }
+            }
+            if rng.chance(40) {
+                let corner = room.corners()[rng.range(0, 4) as usize];
+                if walled_in(&rows, corner) {
+                    let (x, y) = (corner.0, corner.1);
+                    rows[y as usize][x as usize] = '$';
+                    chests.push((x, y, rng.range(1, 4)));
+                }
+            }
+        }
+
+        let (up, up_entry) = stairs_spot(&rows, &rooms[0], None);
+        rows[up.1 as usize][up.0 as usize] = '<';
+        let (down, down_entry) = stairs_spot(&rows, &rooms[rooms.len() - 1], Some(up));
+        rows[down.1 as usize][down.0 as usize] = '>';
+
+        let (up_map, up_to) = match self.above() {
+            Some(above) => (above.name(), "down".to_string()),
+            None => (EXIT_MAP.to_string(), EXIT_ENTRY.to_string()),
+        };
+
+        let mut text = String::new();
+        text.push_str("(\n    legend: {\n");
+        text.push_str("        '#': (color: (0.5, 0.4, 0.4), collider: true),\n");
+        text.push_str("        '.': (color: (0.6, 0.6, 0.6)),\n");
+        text.push_str("        '\"': (color: (0.4, 0.3, 0.5), encounter: Some(0.2)),\n");
+        text.push_str(
+            "        '$': (color: (0.9, 0.8, 0.2), collider: true, trigger: Some(Chest)),\n",
+        );
+        let _ = writeln!(
+            text,
+            "        '<': (color: (0.6, 0.6, 0.9), trigger: Some(Warp(map: {:?}, entry: {:?}))),",
+            up_map, up_to
+        );
+        let _ = writeln!(
+            text,
+            "        '>': (color: (0.6, 0.6, 0.9), trigger: Some(Warp(map: {:?}, entry: \"up\"))),",
+            self.below().name()
+        );
+        text.push_str("    },\n    chests: {\n");
+        for (x, y, repels) in chests {
+            let _ = writeln!(text, "        ({}, {}): [(\"Repel\", {})],", x, y, repels);
+        }
+        text.push_str("    },\n");
+        let _ = writeln!(text, "    spawn: {:?},", up_entry);
+        let _ = writeln!(
+            text,
+            "    entries: {{\n        \"up\": {:?},\n        \"down\": {:?},\n    }},",
+            up_entry, down_entry
+        );
+        //ghosts get more common the deeper the floor
+        let _ = writeln!(text, "    enemies: [(Bat, 3), (Ghost, {})],", self.floor);
+        text.push_str(")\n---\n");
+        for row in rows {
+            text.extend(row);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZES: [(i32, i32); 4] = [(16, 12), (48, 32), (30, 20), (80, 60)];
+
+    #[test]
+    fn same_seed_builds_the_same_floor() {
+        for seed in 0..20 {
+            let floor = DungeonFloor::new(seed, 48, 32);
+            assert_eq!(floor.map_text(), floor.map_text());
+            assert_eq!(floor.map_text(), DungeonFloor::new(seed, 48, 32).map_text());
+        }
+    }
+
+    #[test]
+    fn floors_and_seeds_differ() {
+        let floor = DungeonFloor::new(7, 48, 32);
+        assert_ne!(floor.map_text(), DungeonFloor::new(8, 48, 32).map_text());
+        assert_ne!(floor.map_text(), floor.below().map_text());
+    }
+
+    #[test]
+    fn name_round_trips() {
+        let floor = DungeonFloor {
+            seed: 7,
+            floor: 3,
+            width: 48,
+            height: 32,
+        };
+        assert_eq!(floor.name(), "dungeon:7:3:48x32");
+        assert_eq!(DungeonFloor::from_name(&floor.name()), Some(floor));
+    }
+
+    #[test]
+    fn bad_names_are_not_floors() {
+        for name in [
+            "world.map.txt",
+            "dungeon",
+            "dungeon:7:1",
+            "dungeon:7:0:48x32",
+            "dungeon:x:1:48x32",
+            "dungeon:7:1:48",
+            "dungeon:7:1:8x8",
+            "dungeon:7:1:48x32:extra",
+            "cave:7:1:48x32",
+        ] {
+            assert_eq!(DungeonFloor::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn generated_floors_load() {
+        for seed in 0..50 {
+            for (width, height) in SIZES {
+                for number in 1..4 {
+                    let floor = DungeonFloor {
+                        floor: number,
+                        ..DungeonFloor::new(seed, width, height)
+                    };
+                    let map = floor
+                        .generate()
+                        .unwrap_or_else(|err| panic!("{} does not load: {}", floor.name(), err));
+                    for entry in ["up", "down"] {
+                        assert!(
+                            map.metadata.entries.contains_key(entry),
+                            "{} has no {} entry",
+                            floor.name(),
+                            entry
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    classes::{ClassDef, Classes, Job},
+    inventory::Inventory,
+};
+
+pub struct EquipmentPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum EquipSlot {
+    Weapon,
+    Shield,
+    Armor,
+}
+
+//a piece of gear, it sits in the inventory under its name like every other item
+#[derive(Clone, Deserialize)]
+pub struct EquipmentDef {
+    pub name: String,
+    //what classes list in their equipment, like "Sword" or "Light Armor"
+    pub kind: String,
+    pub slot: EquipSlot,
+    #[serde(default)]
+    pub attack: isize,
+    #[serde(default)]
+    pub defense: isize,
+}
+
+//loaded from assets/equipment.ron
+#[derive(Default)]
+pub struct Armory(pub Vec<EquipmentDef>);
+
+//what the player wears, the best piece for every slot out of the gear the class may use
+//rebuilt whenever the inventory or the class changes, so switching class takes off what the new one can not use
+#[derive(Default)]
+pub struct Equipped {
+    pub gear: BTreeMap<EquipSlot, String>,
+    pub attack: isize,
+    pub defense: isize,
+}
+
+impl Equipped {
+    pub fn choose(class: &ClassDef, armory: &Armory, inventory: &Inventory) -> Self {
+        let mut worn: BTreeMap<EquipSlot, &EquipmentDef> = BTreeMap::new();
+        for gear in armory.0.iter() {
+            if !inventory.items.contains_key(&gear.name) || !class.can_equip(gear) {
+                continue;
+            }
+            let better = worn.get(&gear.slot).map_or(true, |current| {
+                gear.attack + gear.defense > current.attack + current.defense
+            });
+            if better {
+                worn.insert(gear.slot, gear);
+            }
+        }
+        Equipped {
+            gear: worn
+                .iter()
+                .map(|(slot, gear)| (*slot, gear.name.clone()))
+                .collect(),
+            attack: worn.values().map(|gear| gear.attack).sum(),
+            defense: worn.values().map(|gear| gear.defense).sum(),
+        }
+    }
+}
+
+impl Plugin for EquipmentPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_armory())
+            .init_resource::<Equipped>()
+            .add_system(update_equipment);
+    }
+}
+
+fn load_armory() -> Armory {
+    let path = "assets/equipment.ron";
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("Could not read {}: {}", path, err);
+            return Armory::default();
+        }
+    };
+    match ron::from_str::<Vec<EquipmentDef>>(&contents) {
+        Ok(gear) => Armory(gear),
+        Err(err) => {
+            warn!("Could not parse {}: {}", path, err);
+            Armory::default()
+        }
+    }
+}
+
+fn update_equipment(
+    inventory: Res<Inventory>,
+    job: Res<Job>,
+    classes: Res<Classes>,
+    armory: Res<Armory>,
+    mut equipped: ResMut<Equipped>,
+) {
+    if !inventory.is_changed() && !job.is_changed() {
+        return;
+    }
+    *equipped = Equipped::choose(&classes.get(&job.class), &armory, &inventory);
+}
@@ -1,9 +1,13 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use std::time::Duration;
 
-use crate::battle::EnemyType;
+use crate::{battle::EnemyType, player::RUN_MULTIPLIER, TILE_SIZE};
 
 pub struct GraphicsPlugin;
 
+//characters.png holds this many walking characters side by side, three frames each
+pub const CHARACTER_APPEARANCES: usize = 4;
+
 pub struct CharacterSheet {
     pub handle: Handle<TextureAtlas>,
     pub player_up: [usize; 3],
@@ -14,6 +18,7 @@ pub struct CharacterSheet {
     pub ghost_frames: [usize; 3],
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FacingDirection {
     Up,
     Down,
@@ -31,13 +36,203 @@ pub struct FrameAnimation {
     pub timer: Timer,
     pub frames: Vec<usize>,
     pub current_frame: usize,
+    //a one-shot animation stops on its last frame and marks itself finished
+    pub looping: bool,
+    pub finished: bool,
+}
+
+impl FrameAnimation {
+    pub fn new(frames: Vec<usize>, frame_time: f32) -> Self {
+        FrameAnimation {
+            timer: Timer::from_seconds(frame_time, true),
+            frames,
+            current_frame: 0,
+            looping: true,
+            finished: false,
+        }
+    }
+
+    //frame a looping animation shows after running for that long, for tiles sharing one animation without a timer each
+    pub fn frame_at(&self, seconds: f32) -> usize {
+        let step = (seconds / self.timer.duration().as_secs_f32()).max(0.0) as usize;
+        self.frames[step % self.frames.len()]
+    }
+
+    //how long it takes to get back to the first frame
+    pub fn cycle_time(&self) -> f32 {
+        self.timer.duration().as_secs_f32() * self.frames.len() as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnimationState {
+    Idle,
+    Walk,
+    Run,
+    Hurt,
+    Attack,
+}
+
+impl AnimationState {
+    //these play once and then go back to whatever the character was doing before
+    fn is_one_shot(&self) -> bool {
+        matches!(self, AnimationState::Hurt | AnimationState::Attack)
+    }
+}
+
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub frames: Vec<usize>,
+    pub frame_time: f32,
+}
+
+impl AnimationClip {
+    fn new(frames: &[usize], frame_time: f32) -> Self {
+        AnimationClip {
+            frames: frames.to_vec(),
+            frame_time,
+        }
+    }
+}
+
+//picks which clip FrameAnimation plays, movement and battle systems only say what the character is doing
+#[derive(Component)]
+pub struct CharacterAnimation {
+    state: AnimationState,
+    //state to return to once a one-shot clip is over
+    resume: AnimationState,
+    clips: HashMap<AnimationState, AnimationClip>,
+    //false when the clip has to be loaded into FrameAnimation again
+    applied: bool,
+    //clips swapped for the same state, like turning around while walking, keep the current frame
+    keep_frame: bool,
+}
+
+impl CharacterAnimation {
+    pub fn new(clips: HashMap<AnimationState, AnimationClip>) -> Self {
+        CharacterAnimation {
+            state: AnimationState::Idle,
+            resume: AnimationState::Idle,
+            clips,
+            applied: false,
+            keep_frame: false,
+        }
+    }
+
+    //one-shot states always start over, looping ones wait for a one-shot clip to finish
+    pub fn play(&mut self, state: AnimationState) {
+        if state.is_one_shot() {
+            if !self.state.is_one_shot() {
+                self.resume = self.state;
+            }
+            self.state = state;
+            self.applied = false;
+            self.keep_frame = false;
+        } else if self.state.is_one_shot() {
+            self.resume = state;
+        } else if self.state != state {
+            self.state = state;
+            self.applied = false;
+            self.keep_frame = false;
+        }
+    }
+
+    pub fn set_clips(&mut self, clips: HashMap<AnimationState, AnimationClip>) {
+        self.clips = clips;
+        if self.applied {
+            self.applied = false;
+            self.keep_frame = true;
+        }
+    }
+}
+
+//same frames for every state, sped up or slowed down, for characters with a single animation row
+fn clips_from_frames(frames: &[usize], frame_time: f32) -> HashMap<AnimationState, AnimationClip> {
+    let mut clips = HashMap::default();
+    clips.insert(AnimationState::Idle, AnimationClip::new(frames, frame_time));
+    clips.insert(AnimationState::Walk, AnimationClip::new(frames, frame_time));
+    clips.insert(
+        AnimationState::Run,
+        AnimationClip::new(frames, frame_time / 2.0),
+    );
+    clips.insert(
+        AnimationState::Hurt,
+        AnimationClip::new(&[frames[0], frames[2], frames[0], frames[2]], 0.08),
+    );
+    clips.insert(
+        AnimationState::Attack,
+        AnimationClip::new(&[frames[1], frames[2], frames[1]], 0.1),
+    );
+    clips
+}
+
+impl CharacterSheet {
+    //down, left, right and up frames of one of the walking characters
+    //characters.png keeps each character in a block of 3 columns over 4 rows, one row per facing,
+    //so a row of the sheet is one facing of every character and the look picks a block of columns
+    pub fn player_frames(&self, appearance: usize) -> [[usize; 3]; 4] {
+        let columns = 12;
+        let first = appearance * 3;
+        [0, 1, 2, 3].map(|row| {
+            [
+                columns * row + first,
+                columns * row + first + 1,
+                columns * row + first + 2,
+            ]
+        })
+    }
+
+    pub fn select_player(&mut self, appearance: usize) {
+        let [down, left, right, up] = self.player_frames(appearance);
+        self.player_down = down;
+        self.player_left = left;
+        self.player_right = right;
+        self.player_up = up;
+    }
+
+    //a walking character standing still shows the middle frame of its row
+    pub fn player_clips(&self, facing: FacingDirection) -> HashMap<AnimationState, AnimationClip> {
+        let frames = match facing {
+            FacingDirection::Up => self.player_up,
+            FacingDirection::Down => self.player_down,
+            FacingDirection::Left => self.player_left,
+            FacingDirection::Right => self.player_right,
+        };
+        let mut clips = clips_from_frames(&frames, 0.2);
+        clips.insert(AnimationState::Idle, AnimationClip::new(&[frames[1]], 0.2));
+        clips.insert(
+            AnimationState::Run,
+            AnimationClip::new(&frames, 0.2 / RUN_MULTIPLIER),
+        );
+        clips
+    }
+
+    //npcs reuse the walking characters, only facing down
+    pub fn npc_clips(&self, appearance: usize) -> HashMap<AnimationState, AnimationClip> {
+        let frames = self.player_frames(appearance)[0];
+        let mut clips = clips_from_frames(&frames, 0.2);
+        //npcs shuffle slowly in place while waiting for the player
+        clips.insert(
+            AnimationState::Idle,
+            AnimationClip::new(&[frames[1], frames[0], frames[1], frames[2]], 0.6),
+        );
+        clips
+    }
+
+    pub fn enemy_clips(&self, enemy_type: EnemyType) -> HashMap<AnimationState, AnimationClip> {
+        match enemy_type {
+            EnemyType::Bat => clips_from_frames(&self.bat_frames, 0.2),
+            EnemyType::Ghost => clips_from_frames(&self.ghost_frames, 0.2),
+        }
+    }
 }
 
 impl Plugin for GraphicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system_to_stage(StartupStage::PreStartup, Self::load_graphics)
-            .add_system(Self::frame_animation)
-            .add_system(Self::update_player_graphics);
+            .add_system(Self::update_player_graphics.before("animation_state"))
+            .add_system(Self::animation_state_machine.label("animation_state"))
+            .add_system(Self::frame_animation.after("animation_state"));
     }
 }
 
@@ -54,16 +249,8 @@ pub fn spawn_enemy_sprite(
     sprite.custom_size = Some(Vec2::splat(0.5));
 
     let animation = match enemy_type {
-        EnemyType::Bat => FrameAnimation {
-            timer: Timer::from_seconds(0.2, true),
-            frames: characters.bat_frames.to_vec(),
-            current_frame: 0,
-        },
-        EnemyType::Ghost => FrameAnimation {
-            timer: Timer::from_seconds(0.2, true),
-            frames: characters.ghost_frames.to_vec(),
-            current_frame: 0,
-        },
+        EnemyType::Bat => FrameAnimation::new(characters.bat_frames.to_vec(), 0.2),
+        EnemyType::Ghost => FrameAnimation::new(characters.ghost_frames.to_vec(), 0.2),
     };
 
     commands
@@ -77,6 +264,33 @@ pub fn spawn_enemy_sprite(
             ..Default::default()
         })
         .insert(animation)
+        .insert(CharacterAnimation::new(characters.enemy_clips(enemy_type)))
+        .id()
+}
+
+//npcs on the map are characters too, drawn from the character sheet instead of the ascii one
+pub fn spawn_npc_sprite(
+    commands: &mut Commands,
+    characters: &CharacterSheet,
+    appearance: usize,
+    translation: Vec3,
+) -> Entity {
+    let frames = characters.player_frames(appearance)[0];
+    let mut sprite = TextureAtlasSprite::new(frames[1]);
+    sprite.custom_size = Some(Vec2::splat(TILE_SIZE));
+
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: sprite,
+            texture_atlas: characters.handle.clone(),
+            transform: Transform {
+                translation: translation,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(FrameAnimation::new(vec![frames[1]], 0.6))
+        .insert(CharacterAnimation::new(characters.npc_clips(appearance)))
         .id()
 }
 //that is a different approach, impl systems distinctly from helper fn. systems are implemented directly on the plugin.
@@ -112,16 +326,52 @@ impl GraphicsPlugin {
     }
 
     fn update_player_graphics(
-        mut sprites_query: Query<(&PlayerGraphics, &mut FrameAnimation), Changed<PlayerGraphics>>,
+        mut sprites_query: Query<
+            (&PlayerGraphics, &mut CharacterAnimation),
+            Changed<PlayerGraphics>,
+        >,
         characters: Res<CharacterSheet>,
     ) {
         for (graphics, mut animation) in sprites_query.iter_mut() {
-            animation.frames = match graphics.facing {
-                FacingDirection::Up => characters.player_up.to_vec(),
-                FacingDirection::Down => characters.player_down.to_vec(),
-                FacingDirection::Left => characters.player_left.to_vec(),
-                FacingDirection::Right => characters.player_right.to_vec(),
+            animation.set_clips(characters.player_clips(graphics.facing));
+        }
+    }
+
+    //loads the clip of the current state into FrameAnimation and ends one-shot clips
+    fn animation_state_machine(
+        mut sprites_query: Query<(
+            &mut TextureAtlasSprite,
+            &mut FrameAnimation,
+            &mut CharacterAnimation,
+        )>,
+    ) {
+        for (mut sprite, mut frames, mut animation) in sprites_query.iter_mut() {
+            if animation.state.is_one_shot() && frames.finished && animation.applied {
+                let resume = animation.resume;
+                animation.state = resume;
+                animation.applied = false;
+                animation.keep_frame = false;
+            }
+            if animation.applied {
+                continue;
+            }
+
+            let clip = match animation.clips.get(&animation.state) {
+                Some(clip) => clip.clone(),
+                None => continue,
+            };
+            if !animation.keep_frame || frames.current_frame >= clip.frames.len() {
+                frames.current_frame = 0;
+                frames.timer.reset();
             }
+            frames
+                .timer
+                .set_duration(Duration::from_secs_f32(clip.frame_time));
+            frames.looping = !animation.state.is_one_shot();
+            frames.finished = false;
+            frames.frames = clip.frames;
+            sprite.index = frames.frames[frames.current_frame];
+            animation.applied = true;
         }
     }
 
@@ -132,6 +382,10 @@ impl GraphicsPlugin {
         for (mut sprite, mut animation) in sprites_query.iter_mut() {
             animation.timer.tick(time.delta());
             if animation.timer.just_finished() {
+                if !animation.looping && animation.current_frame + 1 >= animation.frames.len() {
+                    animation.finished = true;
+                    continue;
+                }
                 animation.current_frame = (animation.current_frame + 1) % animation.frames.len();
                 sprite.index = animation.frames[animation.current_frame];
             }
@@ -0,0 +1,413 @@
+use bevy::{input::InputSystem, prelude::*};
+use directories::ProjectDirs;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+};
+
+pub struct GameInputPlugin;
+
+//systems read what the player wants to do instead of which key was hit, so keys can be rebound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Run,
+    Confirm,
+    Cancel,
+    Interact,
+    Menu,
+    VolumeUp,
+    VolumeDown,
+    ToggleGrid,
+    UseRepel,
+    Stats,
+    Achievements,
+    Skills,
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Run,
+        Action::Confirm,
+        Action::Cancel,
+        Action::Interact,
+        Action::Menu,
+        Action::VolumeUp,
+        Action::VolumeDown,
+        Action::ToggleGrid,
+        Action::UseRepel,
+        Action::Stats,
+        Action::Achievements,
+        Action::Skills,
+    ];
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub bindings: BTreeMap<Action, Vec<KeyCode>>,
+    #[serde(default)]
+    pub buttons: BTreeMap<Action, Vec<GamepadButtonType>>,
+}
+
+//prompts in text name the keys or the buttons depending on what was touched last
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputDevice {
+    Keyboard,
+    Gamepad,
+}
+
+//left stick after the dead zone, scaled back to 0..1 so walking speed follows the tilt
+#[derive(Default)]
+pub struct AnalogMovement(pub Vec2);
+
+const STICK_DEAD_ZONE: f32 = 0.25;
+//how far the stick has to be pushed to count as a direction press in menus
+const STICK_PRESS: f32 = 0.5;
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let bindings = [
+            (Action::MoveUp, vec![KeyCode::W]),
+            (Action::MoveDown, vec![KeyCode::S]),
+            (Action::MoveLeft, vec![KeyCode::A]),
+            (Action::MoveRight, vec![KeyCode::D]),
+            (Action::Run, vec![KeyCode::LShift]),
+            (Action::Confirm, vec![KeyCode::Space]),
+            (Action::Cancel, vec![KeyCode::Escape]),
+            (Action::Interact, vec![KeyCode::E]),
+            (Action::Menu, vec![KeyCode::Tab]),
+            (Action::VolumeUp, vec![KeyCode::Up]),
+            (Action::VolumeDown, vec![KeyCode::Down]),
+            (Action::ToggleGrid, vec![KeyCode::G]),
+            (Action::UseRepel, vec![KeyCode::R]),
+            (Action::Stats, vec![KeyCode::P]),
+            (Action::Achievements, vec![KeyCode::J]),
+            (Action::Skills, vec![KeyCode::K]),
+        ];
+        let buttons = [
+            (Action::MoveUp, vec![GamepadButtonType::DPadUp]),
+            (Action::MoveDown, vec![GamepadButtonType::DPadDown]),
+            (Action::MoveLeft, vec![GamepadButtonType::DPadLeft]),
+            (Action::MoveRight, vec![GamepadButtonType::DPadRight]),
+            (Action::Run, vec![GamepadButtonType::RightTrigger2]),
+            (Action::Confirm, vec![GamepadButtonType::South]),
+            (Action::Cancel, vec![GamepadButtonType::East]),
+            (Action::Interact, vec![GamepadButtonType::West]),
+            (Action::Menu, vec![GamepadButtonType::Start]),
+            (Action::VolumeUp, vec![GamepadButtonType::RightTrigger]),
+            (Action::VolumeDown, vec![GamepadButtonType::LeftTrigger]),
+            (Action::ToggleGrid, vec![GamepadButtonType::Select]),
+            (Action::UseRepel, vec![GamepadButtonType::North]),
+            (Action::Stats, vec![GamepadButtonType::LeftTrigger2]),
+            (Action::Achievements, vec![GamepadButtonType::RightThumb]),
+            (Action::Skills, vec![GamepadButtonType::LeftThumb]),
+        ];
+        KeyBindings {
+            bindings: bindings.into_iter().collect(),
+            buttons: buttons.into_iter().collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.bindings
+            .get(&action)
+            .map(|keys| keys.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+        self.buttons
+            .get(&action)
+            .map(|buttons| buttons.as_slice())
+            .unwrap_or(&[])
+    }
+
+    //every key or button that triggers more than one action
+    //keys and buttons are checked apart, a key named like a button does not clash with it
+    pub fn conflicts(&self) -> Vec<(String, Vec<Action>)> {
+        let mut key_users: HashMap<KeyCode, Vec<Action>> = HashMap::new();
+        for (action, keys) in self.bindings.iter() {
+            for key in keys {
+                key_users.entry(*key).or_default().push(*action);
+            }
+        }
+        let mut button_users: HashMap<GamepadButtonType, Vec<Action>> = HashMap::new();
+        for (action, buttons) in self.buttons.iter() {
+            for button in buttons {
+                button_users.entry(*button).or_default().push(*action);
+            }
+        }
+        let keys = key_users
+            .into_iter()
+            .map(|(key, actions)| (format!("{:?}", key), actions));
+        let buttons = button_users
+            .into_iter()
+            .map(|(button, actions)| (button_name(button), actions));
+        keys.chain(buttons)
+            .filter(|(_, actions)| actions.len() > 1)
+            .collect()
+    }
+
+    pub fn key_names(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|key| format!("{:?}", key))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn button_names(&self, action: Action) -> String {
+        self.buttons(action)
+            .iter()
+            .map(|button| button_name(*button))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    //what to show in text that tells the player which input to use
+    pub fn prompt(&self, action: Action, device: InputDevice) -> String {
+        match device {
+            InputDevice::Keyboard => self.key_names(action),
+            InputDevice::Gamepad => self.button_names(action),
+        }
+    }
+
+    //the first key or button of the action, short enough to float over a tile
+    pub fn prompt_label(&self, action: Action, device: InputDevice) -> Option<String> {
+        match device {
+            InputDevice::Keyboard => self.keys(action).first().map(|key| key_label(*key)),
+            InputDevice::Gamepad => self
+                .buttons(action)
+                .first()
+                .map(|button| button_label(*button)),
+        }
+    }
+
+    pub fn has_conflict(&self, action: Action) -> bool {
+        self.conflicts()
+            .iter()
+            .any(|(_, actions)| actions.contains(&action))
+    }
+
+    fn load() -> Self {
+        let path = config_path();
+        let mut bindings = match fs::read_to_string(&path) {
+            Ok(contents) => match ron::from_str::<KeyBindings>(&contents) {
+                Ok(bindings) => bindings,
+                Err(err) => {
+                    warn!("Could not parse {}: {}", path.display(), err);
+                    KeyBindings::default()
+                }
+            },
+            //no config yet, keep the defaults
+            Err(_) => KeyBindings::default(),
+        };
+
+        //actions added after the config was written fall back to their default keys
+        let defaults = KeyBindings::default();
+        for (action, keys) in defaults.bindings {
+            bindings.bindings.entry(action).or_insert(keys);
+        }
+        for (action, buttons) in defaults.buttons {
+            bindings.buttons.entry(action).or_insert(buttons);
+        }
+
+        for (input, actions) in bindings.conflicts() {
+            warn!("{} is bound to more than one action: {:?}", input, actions);
+        }
+        bindings
+    }
+
+    pub fn save(&self) {
+        let path = config_path();
+        let contents = match ron::ser::to_string_pretty(self, PrettyConfig::default()) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Could not serialize key bindings: {}", err);
+                return;
+            }
+        };
+
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents));
+        if let Err(err) = written {
+            error!("Could not write {}: {}", path.display(), err);
+        }
+    }
+}
+
+impl Plugin for GameInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(KeyBindings::load())
+            .insert_resource(InputDevice::Keyboard)
+            .init_resource::<AnalogMovement>()
+            .init_resource::<Input<Action>>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.after(InputSystem));
+    }
+}
+
+fn config_path() -> PathBuf {
+    ProjectDirs::from("", "", "noob_bevy")
+        .map(|dirs| dirs.config_dir().join("controls.ron"))
+        .unwrap_or_else(|| PathBuf::from("controls.ron"))
+}
+
+pub fn button_name(button: GamepadButtonType) -> String {
+    //named after the usual face button labels instead of their position
+    match button {
+        GamepadButtonType::South => "A".to_string(),
+        GamepadButtonType::East => "B".to_string(),
+        GamepadButtonType::West => "X".to_string(),
+        GamepadButtonType::North => "Y".to_string(),
+        GamepadButtonType::LeftTrigger => "LB".to_string(),
+        GamepadButtonType::RightTrigger => "RB".to_string(),
+        GamepadButtonType::LeftTrigger2 => "LT".to_string(),
+        GamepadButtonType::RightTrigger2 => "RT".to_string(),
+        GamepadButtonType::Select => "Back".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+//labels float over a tile in the interaction prompt, so they stay a few glyphs long
+pub const PROMPT_LENGTH: usize = 3;
+
+pub fn key_label(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    match key {
+        //the arrows of Ascii.png
+        KeyCode::Up => "\u{18}".to_string(),
+        KeyCode::Down => "\u{19}".to_string(),
+        KeyCode::Right => "\u{1a}".to_string(),
+        KeyCode::Left => "\u{1b}".to_string(),
+        KeyCode::Space => "Spc".to_string(),
+        KeyCode::Return => "Ent".to_string(),
+        KeyCode::Escape => "Esc".to_string(),
+        KeyCode::Back => "Bsp".to_string(),
+        KeyCode::LShift | KeyCode::RShift => "Sft".to_string(),
+        KeyCode::LControl | KeyCode::RControl => "Ctl".to_string(),
+        KeyCode::LAlt | KeyCode::RAlt => "Alt".to_string(),
+        //Key1 and Numpad1 both show up as 1
+        _ if name.starts_with("Key") => name["Key".len()..].to_string(),
+        _ if name.starts_with("Numpad") && name.len() == "Numpad".len() + 1 => {
+            name["Numpad".len()..].to_string()
+        }
+        _ => name.chars().take(PROMPT_LENGTH).collect(),
+    }
+}
+
+pub fn button_label(button: GamepadButtonType) -> String {
+    match button {
+        GamepadButtonType::DPadUp => "\u{18}".to_string(),
+        GamepadButtonType::DPadDown => "\u{19}".to_string(),
+        GamepadButtonType::DPadRight => "\u{1a}".to_string(),
+        GamepadButtonType::DPadLeft => "\u{1b}".to_string(),
+        GamepadButtonType::Start => "Str".to_string(),
+        GamepadButtonType::Select => "Bck".to_string(),
+        GamepadButtonType::LeftThumb => "LS".to_string(),
+        GamepadButtonType::RightThumb => "RS".to_string(),
+        other => button_name(other).chars().take(PROMPT_LENGTH).collect(),
+    }
+}
+
+fn apply_dead_zone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+    if length < STICK_DEAD_ZONE {
+        return Vec2::ZERO;
+    }
+    stick / length * ((length - STICK_DEAD_ZONE) / (1.0 - STICK_DEAD_ZONE)).min(1.0)
+}
+
+fn stick_points_to(stick: Vec2, action: Action) -> bool {
+    match action {
+        Action::MoveUp => stick.y > STICK_PRESS,
+        Action::MoveDown => stick.y < -STICK_PRESS,
+        Action::MoveLeft => stick.x < -STICK_PRESS,
+        Action::MoveRight => stick.x > STICK_PRESS,
+        _ => false,
+    }
+}
+
+//mirrors the keyboard and gamepads into Input<Action> so just_pressed and pressed work the same way as for keys
+//actions only start on a fresh press, a key that is still held down while being rebound does not fire its new action
+#[allow(clippy::too_many_arguments)]
+fn update_actions(
+    keyboard: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    bindings: Res<KeyBindings>,
+    mut actions: ResMut<Input<Action>>,
+    mut analog: ResMut<AnalogMovement>,
+    mut device: ResMut<InputDevice>,
+) {
+    let mut stick = Vec2::ZERO;
+    for gamepad in gamepads.iter().cloned() {
+        let x = gamepad_axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        let y = gamepad_axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+            .unwrap_or(0.0);
+        let gamepad_stick = apply_dead_zone(Vec2::new(x, y));
+        if gamepad_stick.length() > stick.length() {
+            stick = gamepad_stick;
+        }
+    }
+    analog.0 = stick;
+
+    let used = if gamepad_buttons.get_just_pressed().next().is_some() || stick != Vec2::ZERO {
+        Some(InputDevice::Gamepad)
+    } else if keyboard.get_just_pressed().next().is_some() {
+        Some(InputDevice::Keyboard)
+    } else {
+        None
+    };
+    //only written on a switch so prompts are not redrawn every frame
+    if let Some(used) = used {
+        if *device != used {
+            *device = used;
+        }
+    }
+
+    actions.clear();
+    for action in Action::ALL {
+        let keys = bindings.keys(action);
+        let buttons = bindings.buttons(action);
+        let pushed = stick_points_to(stick, action);
+
+        let started = keys.iter().any(|key| keyboard.just_pressed(*key))
+            || gamepads.iter().cloned().any(|gamepad| {
+                buttons.iter().any(|button| {
+                    gamepad_buttons.just_pressed(GamepadButton::new(gamepad, *button))
+                })
+            })
+            || (pushed && !actions.pressed(action));
+        let held = keys.iter().any(|key| keyboard.pressed(*key))
+            || gamepads.iter().cloned().any(|gamepad| {
+                buttons
+                    .iter()
+                    .any(|button| gamepad_buttons.pressed(GamepadButton::new(gamepad, *button)))
+            })
+            || pushed;
+
+        if started {
+            actions.press(action);
+        } else if actions.pressed(action) && !held {
+            actions.release(action);
+        }
+    }
+}
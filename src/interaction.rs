@@ -0,0 +1,341 @@
+use bevy::prelude::*;
+
+use crate::{
+    ascii::{spawn_ascii_sprite, AsciiSpriteSheet, NineSliceIndices},
+    graphics::{FacingDirection, PlayerGraphics},
+    input::{Action, InputDevice, KeyBindings, PROMPT_LENGTH},
+    inventory::Inventory,
+    npc::spawn_textbox,
+    player::Player,
+    save::AutosaveEvent,
+    story::StoryFlags,
+    tilemap::{world_to_tile, TileCollider, TileGrid},
+    GameState, MainCamera, TILE_SIZE,
+};
+
+pub struct InteractionPlugin;
+
+//what happens when the player faces this tile and presses interact, each kind is handled by its own system
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub enum Interactable {
+    Talk,
+    ReadSign,
+    UseDoor,
+    OpenChest,
+}
+
+#[derive(Component)]
+pub struct Sign(pub String);
+
+pub struct InteractEvent {
+    pub target: Entity,
+    pub kind: Interactable,
+}
+
+//label of the interact key floating over whatever the player would interact with
+#[derive(Component)]
+struct InteractionPrompt;
+
+//one glyph of the prompt, the ones past the end of the label are hidden
+#[derive(Component)]
+struct PromptGlyph;
+
+//items inside and the story flag that marks it as opened, emptied chests lose this component
+#[derive(Component)]
+pub struct Chest {
+    pub contents: Vec<(String, usize)>,
+    pub flag: String,
+}
+
+//story flag that keeps the door open when the map is visited again
+#[derive(Component)]
+pub struct Door {
+    pub flag: String,
+}
+
+pub const OPEN_DOOR_GLYPH: char = '\'';
+pub const OPEN_CHEST_GLYPH: char = '_';
+
+impl Plugin for InteractionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<InteractEvent>()
+            .add_startup_system(spawn_interaction_prompt)
+            .add_system_set(
+                SystemSet::on_update(GameState::Overworld)
+                    .with_system(interact.label("interact").before("speech"))
+                    .with_system(update_interaction_prompt.after("movement"))
+                    .with_system(read_sign.after("interact").label("speech"))
+                    .with_system(use_door.after("interact"))
+                    .with_system(open_chest.after("interact").label("speech")),
+            )
+            .add_system_set(
+                SystemSet::on_pause(GameState::Overworld).with_system(hide_interaction_prompt),
+            );
+    }
+}
+
+//chests are remembered per map and tile, so the same layout in another map is a different chest
+pub fn chest_flag(map: &str, x: usize, y: usize) -> String {
+    format!("chest:{}:{},{}", map, x, y)
+}
+
+pub fn door_flag(map: &str, x: usize, y: usize) -> String {
+    format!("door:{}:{},{}", map, x, y)
+}
+
+fn facing_offset(facing: &FacingDirection) -> IVec2 {
+    //tile rows grow downwards, so looking up means going back a row
+    match facing {
+        FacingDirection::Up => IVec2::new(0, -1),
+        FacingDirection::Down => IVec2::new(0, 1),
+        FacingDirection::Left => IVec2::new(-1, 0),
+        FacingDirection::Right => IVec2::new(1, 0),
+    }
+}
+
+fn faced_interactable(
+    transform: &Transform,
+    graphics: &PlayerGraphics,
+    grid: &TileGrid,
+    interactable_query: &Query<(&Interactable, &Transform), Without<Player>>,
+) -> Option<Entity> {
+    let tile = world_to_tile(transform.translation) + facing_offset(&graphics.facing);
+    grid.at(tile)
+        .iter()
+        .copied()
+        .find(|entity| interactable_query.get(*entity).is_ok())
+}
+
+fn interact(
+    player_query: Query<(&Player, &Transform, &PlayerGraphics)>,
+    interactable_query: Query<(&Interactable, &Transform), Without<Player>>,
+    grid: Res<TileGrid>,
+    actions: Res<Input<Action>>,
+    mut interact_event: EventWriter<InteractEvent>,
+) {
+    let (player, transform, graphics) = player_query.single();
+    //in grid mode the player can only interact once a step has been completed
+    if !player.active || player.mid_step() || !actions.just_pressed(Action::Interact) {
+        return;
+    }
+
+    if let Some(target) = faced_interactable(transform, graphics, &grid, &interactable_query) {
+        let (kind, _) = interactable_query.get(target).unwrap();
+        interact_event.send(InteractEvent {
+            target,
+            kind: *kind,
+        });
+    }
+}
+
+fn spawn_interaction_prompt(mut commands: Commands, ascii: Res<AsciiSpriteSheet>) {
+    let glyphs: Vec<Entity> = (0..PROMPT_LENGTH)
+        .map(|_| {
+            let glyph = spawn_ascii_sprite(
+                &mut commands,
+                &ascii,
+                '!' as usize,
+                Color::rgb(0.9, 0.9, 0.3),
+                Vec3::ZERO,
+                Vec3::splat(1.0),
+            );
+            commands.entity(glyph).insert(PromptGlyph).id()
+        })
+        .collect();
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 950.0),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(InteractionPrompt)
+        .insert(Name::new("Interaction Prompt"))
+        .push_children(&glyphs);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_interaction_prompt(
+    player_query: Query<(&Player, &Transform, &PlayerGraphics)>,
+    interactable_query: Query<(&Interactable, &Transform), Without<Player>>,
+    mut prompt_query: Query<
+        (&mut Transform, &mut Visibility, &Children),
+        (
+            With<InteractionPrompt>,
+            Without<Player>,
+            Without<Interactable>,
+        ),
+    >,
+    mut glyph_query: Query<
+        (&mut Transform, &mut Visibility, &mut TextureAtlasSprite),
+        (
+            With<PromptGlyph>,
+            Without<InteractionPrompt>,
+            Without<Player>,
+            Without<Interactable>,
+        ),
+    >,
+    grid: Res<TileGrid>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+) {
+    let (player, transform, graphics) = player_query.single();
+    let (mut prompt_transform, mut visibility, children) = prompt_query.single_mut();
+
+    let target = if player.active {
+        faced_interactable(transform, graphics, &grid, &interactable_query)
+    } else {
+        None
+    };
+
+    match target.and_then(|target| interactable_query.get(target).ok()) {
+        Some((_, target_transform)) => {
+            prompt_transform.translation.x = target_transform.translation.x;
+            prompt_transform.translation.y = target_transform.translation.y + TILE_SIZE;
+            visibility.is_visible = true;
+            //spells out the key or button to press, centered over the tile
+            let mut label: Vec<char> = bindings
+                .prompt_label(Action::Interact, *device)
+                .unwrap_or_default()
+                .chars()
+                .filter(char::is_ascii)
+                .take(PROMPT_LENGTH)
+                .collect();
+            if label.is_empty() {
+                label.push('!');
+            }
+            let left = -((label.len() - 1) as f32) * TILE_SIZE / 2.0;
+            for (i, child) in children.iter().enumerate() {
+                if let Ok((mut glyph_transform, mut glyph_visibility, mut sprite)) =
+                    glyph_query.get_mut(*child)
+                {
+                    match label.get(i) {
+                        Some(glyph) => {
+                            sprite.index = *glyph as usize;
+                            glyph_transform.translation.x = left + i as f32 * TILE_SIZE;
+                            glyph_visibility.is_visible = true;
+                        }
+                        None => glyph_visibility.is_visible = false,
+                    }
+                }
+            }
+        }
+        None => visibility.is_visible = false,
+    }
+}
+
+fn hide_interaction_prompt(mut prompt_query: Query<&mut Visibility, With<InteractionPrompt>>) {
+    for mut visibility in prompt_query.iter_mut() {
+        visibility.is_visible = false;
+    }
+}
+
+fn read_sign(
+    mut commands: Commands,
+    mut interact_event: EventReader<InteractEvent>,
+    mut player_query: Query<&mut Player>,
+    sign_query: Query<&Sign>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+    indices: Res<NineSliceIndices>,
+) {
+    for event in interact_event.iter() {
+        if event.kind != Interactable::ReadSign {
+            continue;
+        }
+        if let Ok(sign) = sign_query.get(event.target) {
+            player_query.single_mut().active = false;
+            let camera_transform = camera_query.single();
+            spawn_textbox(
+                &mut commands,
+                &ascii,
+                &indices,
+                Vec2::new(0.0, 1.0 - 1.5 * TILE_SIZE) + camera_transform.translation.truncate(),
+                &sign.0,
+            );
+        }
+    }
+}
+
+//doors open for good, the tile stops blocking and turns into the open glyph
+fn use_door(
+    mut commands: Commands,
+    mut interact_event: EventReader<InteractEvent>,
+    mut sprite_query: Query<&mut TextureAtlasSprite>,
+    door_query: Query<&Door>,
+    mut story: ResMut<StoryFlags>,
+    mut autosave: EventWriter<AutosaveEvent>,
+) {
+    for event in interact_event.iter() {
+        if event.kind != Interactable::UseDoor {
+            continue;
+        }
+        if let Ok(mut sprite) = sprite_query.get_mut(event.target) {
+            sprite.index = OPEN_DOOR_GLYPH as usize;
+        }
+        if let Ok(door) = door_query.get(event.target) {
+            story.set(&door.flag);
+            autosave.send(AutosaveEvent);
+        }
+        commands
+            .entity(event.target)
+            .remove::<TileCollider>()
+            .remove::<Interactable>();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn open_chest(
+    mut commands: Commands,
+    mut interact_event: EventReader<InteractEvent>,
+    mut player_query: Query<&mut Player>,
+    mut chest_query: Query<(&Chest, &mut TextureAtlasSprite)>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    mut inventory: ResMut<Inventory>,
+    mut story: ResMut<StoryFlags>,
+    mut autosave: EventWriter<AutosaveEvent>,
+    ascii: Res<AsciiSpriteSheet>,
+    indices: Res<NineSliceIndices>,
+) {
+    for event in interact_event.iter() {
+        if event.kind != Interactable::OpenChest {
+            continue;
+        }
+        let (chest, mut sprite) = match chest_query.get_mut(event.target) {
+            Ok(chest) => chest,
+            Err(_) => continue,
+        };
+
+        sprite.index = OPEN_CHEST_GLYPH as usize;
+        for (item, amount) in chest.contents.iter() {
+            inventory.add(item, *amount);
+        }
+        story.set(&chest.flag);
+        autosave.send(AutosaveEvent);
+
+        let found = chest
+            .contents
+            .iter()
+            .map(|(item, amount)| format!("{} {}", amount, item))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let text = if found.is_empty() {
+            "The chest is empty.".to_string()
+        } else {
+            format!("Found {}!", found)
+        };
+
+        player_query.single_mut().active = false;
+        let camera_transform = camera_query.single();
+        spawn_textbox(
+            &mut commands,
+            &ascii,
+            &indices,
+            Vec2::new(0.0, 1.0 - 1.5 * TILE_SIZE) + camera_transform.translation.truncate(),
+            &text,
+        );
+        commands
+            .entity(event.target)
+            .remove::<Chest>()
+            .remove::<Interactable>();
+    }
+}
@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub struct InventoryPlugin;
+
+//items are keyed by name so the save file stays readable, a BTreeMap keeps them sorted on disk
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    pub items: BTreeMap<String, usize>,
+}
+
+impl Inventory {
+    pub fn add(&mut self, item: &str, amount: usize) {
+        *self.items.entry(item.to_string()).or_default() += amount;
+    }
+
+    pub fn remove(&mut self, item: &str, amount: usize) -> bool {
+        match self.items.get_mut(item) {
+            Some(count) if *count >= amount => {
+                *count -= amount;
+                if *count == 0 {
+                    self.items.remove(item);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Inventory>();
+    }
+}
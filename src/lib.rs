@@ -0,0 +1,51 @@
+#![allow(clippy::redundant_field_names)]
+//the game itself, main.rs only puts the plugins together so tools and benchmarks can reuse the modules
+use bevy::prelude::*;
+
+pub mod achievements;
+pub mod ascii;
+pub mod audio;
+pub mod battle;
+pub mod character_creation;
+pub mod classes;
+pub mod controls_menu;
+pub mod debug;
+pub mod dungeon;
+pub mod equipment;
+pub mod fadeout;
+pub mod graphics;
+pub mod input;
+pub mod interaction;
+pub mod inventory;
+pub mod npc;
+pub mod play_stats;
+pub mod player;
+pub mod save;
+pub mod skill_tree;
+pub mod start_menu;
+pub mod story;
+pub mod tile_chunks;
+pub mod tiled;
+pub mod tilemap;
+
+pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
+pub const RESOLUTION: f32 = 16.0 / 9.0;
+pub const TILE_SIZE: f32 = 0.1;
+
+#[derive(Debug, Clone, Eq, PartialEq, Copy, Hash)]
+pub enum GameState {
+    Overworld,
+    Battle,
+    StartMenu,
+    Controls,
+    Stats,
+    Achievements,
+    CharacterCreation,
+    JobChange,
+    SkillTree,
+    //the screen is dark while the player moves to another map
+    Warp,
+}
+
+#[derive(Component)]
+pub struct MainCamera;
@@ -1,49 +1,21 @@
 #![allow(clippy::redundant_field_names)]
+use bevy::asset::AssetServerSettings;
 use bevy::prelude::*;
 use bevy::render::{camera::ScalingMode, texture::ImageSettings};
 use bevy::time::*;
 use bevy::window::PresentMode;
 
-mod player;
-use fadeout::FadeoutPlugin;
-use player::PlayerPlugin;
-
-mod debug;
-use debug::DebugPlugin;
-
-mod ascii;
-use ascii::AsciiPlugin;
-
-mod tilemap;
-use tilemap::TileMapPlugin;
-
-mod battle;
-use battle::BattlePlugin;
-
-mod fadeout;
-
-mod audio;
-use audio::GameAudioPlugin;
-
-mod graphics;
-use graphics::GraphicsPlugin;
-
-mod start_menu;
-use start_menu::MainMenuPlugin;
-
-mod npc;
-use npc::NpcPlugin;
-
-pub const CLEAR: Color = Color::rgb(0.1, 0.1, 0.1);
-pub const RESOLUTION: f32 = 16.0 / 9.0;
-pub const TILE_SIZE: f32 = 0.1;
-
-#[derive(Debug, Clone, Eq, PartialEq, Copy, Hash)]
-pub enum GameState {
-    Overworld,
-    Battle,
-    StartMenu,
-}
+use noob_bevy::{
+    achievements::AchievementsPlugin, ascii::AsciiPlugin, audio::GameAudioPlugin,
+    battle::BattlePlugin, character_creation::CharacterCreationPlugin, classes::ClassesPlugin,
+    controls_menu::ControlsMenuPlugin, debug::DebugPlugin, equipment::EquipmentPlugin,
+    fadeout::FadeoutPlugin, graphics::GraphicsPlugin, input::GameInputPlugin,
+    interaction::InteractionPlugin, inventory::InventoryPlugin, npc::NpcPlugin,
+    play_stats::PlayStatsPlugin, player::PlayerPlugin, save::SavePlugin,
+    skill_tree::SkillTreePlugin, start_menu::MainMenuPlugin, story::StoryPlugin,
+    tile_chunks::TileChunkPlugin, tiled::TiledPlugin, tilemap::TileMapPlugin, GameState,
+    MainCamera, CLEAR, RESOLUTION,
+};
 
 fn main() {
     let height = 900.0;
@@ -53,6 +25,12 @@ fn main() {
         //bevy 0.8 now uses linear texture filtering by default, but we can change it's global default for textures that requires unfiltered pixels(pixel art).
         .insert_resource(ImageSettings::default_nearest())
         .insert_resource(ClearColor(CLEAR))
+        //maps respawn as soon as their file is saved, needs the filesystem_watcher feature
+        //only while developing, release builds do not watch the assets folder
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: cfg!(debug_assertions),
+            ..Default::default()
+        })
         .insert_resource(WindowDescriptor {
             title: "A Noob Bevy Game".to_string(),
             width: height * RESOLUTION,
@@ -75,12 +53,23 @@ fn main() {
         .add_plugin(DebugPlugin)
         .add_plugin(MainMenuPlugin)
         .add_plugin(TileMapPlugin)
+        .add_plugin(InventoryPlugin)
+        .add_plugin(EquipmentPlugin)
+        .add_plugin(StoryPlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(GameInputPlugin)
+        .add_plugin(ControlsMenuPlugin)
+        .add_plugin(InteractionPlugin)
+        .add_plugin(PlayStatsPlugin)
+        .add_plugin(AchievementsPlugin)
+        .add_plugin(CharacterCreationPlugin)
+        .add_plugin(ClassesPlugin)
+        .add_plugin(SkillTreePlugin)
+        .add_plugin(TiledPlugin)
+        .add_plugin(TileChunkPlugin)
         .run();
 }
 
-#[derive(Component)]
-pub struct MainCamera;
-
 //commands run at the end of the frame, it is the place to put things that need to be done every frame, like a queue of tasks - Commands are executed after the game update logic runs, but before rendering occurs (in CoreStage::Update in the ECS schedule) . So if you spawn something with a command, it will be rendered without any delay. But if you want to access the spawned components, you will either need to access them after the CoreStage::Update stage (for the current frame), or wait until next frame.
 fn spawn_camera(mut commands: Commands) {
     let mut camera = Camera2dBundle::default();
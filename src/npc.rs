@@ -1,11 +1,17 @@
 use bevy::prelude::*;
+use serde::Deserialize;
 
 use crate::{
     ascii::{
         spawn_ascii_sprite, spawn_ascii_text, spawn_nine_slice, AsciiSpriteSheet, NineSliceIndices,
     },
     battle::BattleStats,
+    character_creation::Hero,
+    input::Action,
+    interaction::{InteractEvent, Interactable},
+    play_stats::PlayStats,
     player::Player,
+    save::AutosaveEvent,
     GameState, MainCamera, CLEAR, TILE_SIZE,
 };
 
@@ -14,16 +20,29 @@ pub struct NpcPlugin;
 #[derive(Component)]
 pub struct NpcText;
 
-#[derive(Component)]
+//named in the legend of map files
+#[derive(Component, Clone, Copy, Deserialize)]
 pub enum Npc {
     Healer,
+    //opens the class menu
+    JobChanger,
+}
+
+impl Npc {
+    //which character of characters.png stands in for the npc
+    pub fn appearance(&self) -> usize {
+        match self {
+            Npc::Healer => 0,
+            Npc::JobChanger => 2,
+        }
+    }
 }
 
 impl Plugin for NpcPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(
             SystemSet::on_update(GameState::Overworld)
-                .with_system(npc_speech.label("speech"))
+                .with_system(npc_speech.after("interact").label("speech"))
                 .with_system(clear_speech.after("speech")),
         );
     }
@@ -33,10 +52,10 @@ fn clear_speech(
     mut commands: Commands,
     mut player_query: Query<&mut Player>,
     speech_query: Query<Entity, With<NpcText>>,
-    keyboard: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
 ) {
     let mut player = player_query.single_mut();
-    if keyboard.any_just_pressed([KeyCode::E, KeyCode::Space]) {
+    if actions.any_just_pressed([Action::Interact, Action::Confirm]) {
         for ent in speech_query.iter() {
             player.active = true;
             commands.entity(ent).despawn_recursive();
@@ -44,7 +63,23 @@ fn clear_speech(
     }
 }
 
-fn spawn_textbox(
+pub fn spawn_textbox(
+    commands: &mut Commands,
+    ascii: &AsciiSpriteSheet,
+    indices: &NineSliceIndices,
+    translation: Vec2,
+    text: &str,
+) -> Entity {
+    let textbox = spawn_text_panel(commands, ascii, indices, translation, text);
+    commands
+        .entity(textbox)
+        .insert(Name::new("Npc Text"))
+        .insert(NpcText)
+        .id()
+}
+
+//a framed line of text, without anything that closes it on a key press
+pub fn spawn_text_panel(
     commands: &mut Commands,
     ascii: &AsciiSpriteSheet,
     indices: &NineSliceIndices,
@@ -71,47 +106,56 @@ fn spawn_textbox(
             translation: translation.extend(900.0),
             ..Default::default()
         })
-        .insert(Name::new("Npc Text"))
-        .insert(NpcText)
         .add_child(text)
         .add_child(background)
         .add_child(text_nine_slice)
         .id()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn npc_speech(
     mut commands: Commands,
-    mut player_query: Query<(&mut Player, &mut BattleStats, &Transform)>,
+    mut interact_event: EventReader<InteractEvent>,
+    mut player_query: Query<(&mut Player, &mut BattleStats)>,
     camera_query: Query<&Transform, With<MainCamera>>,
-    npc_query: Query<(&Npc, &Transform)>,
-    keyboard: Res<Input<KeyCode>>,
+    npc_query: Query<&Npc>,
     ascii: Res<AsciiSpriteSheet>,
     indices: Res<NineSliceIndices>,
+    mut autosave: EventWriter<AutosaveEvent>,
+    mut play_stats: ResMut<PlayStats>,
+    hero: Res<Hero>,
+    mut state: ResMut<State<GameState>>,
 ) {
-    let (mut player, mut stats, transform) = player_query.single_mut();
+    let (mut player, mut stats) = player_query.single_mut();
     let camera_transform = camera_query.single();
-    if !player.active {
-        return;
-    }
 
-    if keyboard.just_pressed(KeyCode::E) {
-        for (_npc, npc_transform) in npc_query.iter() {
-            if Vec2::distance(
-                npc_transform.translation.truncate(),
-                transform.translation.truncate(),
-            ) < TILE_SIZE * 1.5
-            {
+    for event in interact_event.iter() {
+        if event.kind != Interactable::Talk {
+            continue;
+        }
+        let npc = match npc_query.get(event.target) {
+            Ok(npc) => npc,
+            Err(_) => continue,
+        };
+
+        match npc {
+            Npc::Healer => {
                 player.active = false;
                 stats.health = stats.max_health;
+                play_stats.heals_used += 1;
+                autosave.send(AutosaveEvent);
 
                 spawn_textbox(
                     &mut commands,
                     &ascii,
                     &indices,
                     Vec2::new(0.0, 1.0 - 1.5 * TILE_SIZE) + camera_transform.translation.truncate(),
-                    "You seem weak, let me heal you!",
+                    &format!("You seem weak, {}! Let me heal you.", hero.name),
                 );
             }
+            Npc::JobChanger => {
+                let _ = state.push(GameState::JobChange);
+            }
         }
     }
 }
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet},
+    battle::EnemyType,
+    input::{Action, InputDevice, KeyBindings},
+    player::Player,
+    GameState, MainCamera, RESOLUTION, TILE_SIZE,
+};
+
+pub struct PlayStatsPlugin;
+
+//record of what happened in this playthrough, saved alongside the player
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct PlayStats {
+    pub steps: usize,
+    pub battles_won: usize,
+    pub battles_fled: usize,
+    pub battles_lost: usize,
+    pub damage_dealt: usize,
+    pub damage_taken: usize,
+    pub exp_earned: usize,
+    pub enemies_defeated: BTreeMap<EnemyType, usize>,
+    pub heals_used: usize,
+    //in seconds, anything but the start menu counts
+    pub play_time: f32,
+}
+
+#[derive(Component)]
+struct StatsText;
+
+impl Plugin for PlayStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayStats>()
+            .add_system(count_play_time)
+            .add_system_set(
+                SystemSet::on_update(GameState::Overworld).with_system(open_stats_screen),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Stats)
+                    .with_system(stats_screen_input)
+                    .with_system(draw_stats_screen),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Stats).with_system(despawn_stats_text));
+    }
+}
+
+fn count_play_time(time: Res<Time>, state: Res<State<GameState>>, mut stats: ResMut<PlayStats>) {
+    if *state.current() != GameState::StartMenu {
+        stats.play_time += time.delta_seconds();
+    }
+}
+
+//the new state runs its systems in the same frame, without the clear the press that opened the screen closes it again
+fn open_stats_screen(
+    player_query: Query<&Player>,
+    mut actions: ResMut<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let player = player_query.single();
+    if player.active && actions.just_pressed(Action::Stats) {
+        actions.clear();
+        let _ = state.push(GameState::Stats);
+    }
+}
+
+fn stats_screen_input(mut actions: ResMut<Input<Action>>, mut state: ResMut<State<GameState>>) {
+    if actions.any_just_pressed([Action::Cancel, Action::Stats]) {
+        actions.clear();
+        let _ = state.pop();
+    }
+}
+
+fn format_play_time(seconds: f32) -> String {
+    let seconds = seconds as usize;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+//redrawn every second so the play time keeps ticking while the screen is open
+#[allow(clippy::too_many_arguments)]
+fn draw_stats_screen(
+    mut commands: Commands,
+    mut shown_seconds: Local<usize>,
+    stats: Res<PlayStats>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<StatsText>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    let seconds = stats.play_time as usize;
+    if !text_query.is_empty() && *shown_seconds == seconds && !device.is_changed() {
+        return;
+    }
+    *shown_seconds = seconds;
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let camera = camera_query.single().translation;
+    let left = camera.x - RESOLUTION + TILE_SIZE;
+    let top = camera.y + 1.0 - TILE_SIZE;
+
+    let mut lines = vec![
+        "Stats".to_string(),
+        String::new(),
+        format!("{:<16}{}", "Play time", format_play_time(stats.play_time)),
+        format!("{:<16}{}", "Steps", stats.steps),
+        format!("{:<16}{}", "Battles won", stats.battles_won),
+        format!("{:<16}{}", "Battles fled", stats.battles_fled),
+        format!("{:<16}{}", "Battles lost", stats.battles_lost),
+        format!("{:<16}{}", "Damage dealt", stats.damage_dealt),
+        format!("{:<16}{}", "Damage taken", stats.damage_taken),
+        format!("{:<16}{}", "Exp earned", stats.exp_earned),
+        format!("{:<16}{}", "Heals used", stats.heals_used),
+        String::new(),
+        "Defeated".to_string(),
+    ];
+    if stats.enemies_defeated.is_empty() {
+        lines.push("  nothing yet".to_string());
+    }
+    for (enemy_type, count) in stats.enemies_defeated.iter() {
+        let name = format!("{:?}", enemy_type);
+        lines.push(format!("  {:<14}{}", name, count));
+    }
+    lines.push(String::new());
+    lines.push(format!("{} back", bindings.prompt(Action::Cancel, *device)));
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = spawn_ascii_text(
+            &mut commands,
+            &ascii,
+            line,
+            Vec3::new(left, top - i as f32 * TILE_SIZE, 900.0),
+        );
+        commands.entity(text).insert(StatsText);
+    }
+}
+
+fn despawn_stats_text(mut commands: Commands, text_query: Query<Entity, With<StatsText>>) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -5,9 +5,18 @@ use bevy_inspector_egui::Inspectable;
 use crate::{
     ascii::AsciiSpriteSheet,
     battle::BattleStats,
+    classes::{ClassDef, Classes, Job},
     fadeout::create_fadeout,
-    graphics::{CharacterSheet, FacingDirection, FrameAnimation, PlayerGraphics},
-    tilemap::{EncounterSpawner, TileCollider},
+    graphics::{
+        AnimationState, CharacterAnimation, CharacterSheet, FacingDirection, FrameAnimation,
+        PlayerGraphics,
+    },
+    input::{Action, AnalogMovement},
+    inventory::Inventory,
+    play_stats::PlayStats,
+    save::PendingLoad,
+    skill_tree::SkillBonuses,
+    tilemap::{world_to_tile, TileCollider, TileGrid},
     GameState, MainCamera, TILE_SIZE,
 };
 
@@ -16,45 +25,98 @@ pub struct PlayerPlugin;
 #[derive(Component, Default, Reflect)]
 #[reflect(Component)]
 pub struct EncounterTracker {
-    timer: Timer,
+    //walked distance that has not made up a full tile yet, for free movement
+    distance: f32,
+    last_position: Vec2,
+    //steps left without random battles, after a fight and while a repel is active
+    safe_steps: usize,
+    repel_steps: usize,
 }
 
+const SAFE_STEPS_AFTER_BATTLE: usize = 8;
+const REPEL_STEPS: usize = 50;
+pub const REPEL_ITEM: &str = "Repel";
+
+pub const RUN_MULTIPLIER: f32 = 1.8;
+pub const SKILL_POINTS_PER_LEVEL: usize = 1;
+//above the ground and objects layers of the map, below the overhead one
+pub const PLAYER_Z: f32 = 500.0;
+//how far off a corner the player can be and still get nudged around it
+const CORNER_TOLERANCE: f32 = TILE_SIZE * 0.4;
+
 //make the player a unique component to be able to access it from all the entities  in the game, not a simple texture atlas sprite
 #[derive(Component, Inspectable)]
 pub struct Player {
     speed: f32,
     pub active: bool,
     just_moved: bool,
+    running: bool,
     pub exp: usize,
+    pub level: usize,
+    //spent on the skill tree, kept when changing class
+    pub skill_points: usize,
+    //start and end of the tile step in progress when using grid movement
+    #[inspectable(ignore)]
+    step: Option<(Vec3, Vec3)>,
+    step_progress: f32,
+}
+
+//free movement slides around the map, grid movement walks exactly one tile per key press
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum MovementMode {
+    Free,
+    Grid,
 }
 
 impl Player {
-    pub fn give_exp(&mut self, exp: usize, stats: &mut BattleStats) -> bool {
+    pub fn give_exp(&mut self, exp: usize, stats: &mut BattleStats, class: &ClassDef) -> bool {
         self.exp += exp;
         if self.exp >= 50 {
-            stats.health += 2;
-            stats.max_health += 2;
-            stats.attack += 1;
-            stats.defense += 1;
+            let growth = class.growth_at(self.level);
+            stats.health += growth.health;
+            stats.max_health += growth.health;
+            stats.attack += growth.attack;
+            stats.defense += growth.defense;
             self.exp -= 50;
+            self.level += 1;
+            self.skill_points += SKILL_POINTS_PER_LEVEL;
             return true;
         }
         false
     }
+
+    pub fn mid_step(&self) -> bool {
+        self.step.is_some()
+    }
+
+    //tiles per second, holding the run button speeds walking up
+    fn current_speed(&self) -> f32 {
+        if self.running {
+            self.speed * RUN_MULTIPLIER
+        } else {
+            self.speed
+        }
+    }
 }
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_resume(GameState::Overworld).with_system(show_player))
+        app.insert_resource(MovementMode::Free)
+            .add_system_set(SystemSet::on_resume(GameState::Overworld).with_system(show_player))
+            .add_system_set(SystemSet::on_exit(GameState::Battle).with_system(start_safe_window))
             .add_system_set(SystemSet::on_pause(GameState::Overworld).with_system(hide_player))
             .add_system_set(
                 SystemSet::on_update(GameState::Overworld)
                     .with_system(player_encounter_checking.after("movement"))
                     //labelling to enforce right sort avoiding camera jittering when the player is moving
                     .with_system(camera_follow.after("movement"))
+                    .with_system(toggle_movement_mode.before("movement"))
+                    .with_system(use_repel)
                     .with_system(player_movement.label("movement")),
             )
-            .add_system_set(SystemSet::on_enter(GameState::Overworld).with_system(spawn_player));
+            .add_system_set(
+                SystemSet::on_enter(GameState::Overworld).with_system(spawn_player.after("load")),
+            );
     }
 }
 
@@ -96,26 +158,74 @@ fn show_player(
 fn player_encounter_checking(
     mut commands: Commands,
     mut player_query: Query<(&mut Player, &mut EncounterTracker, &Transform)>,
-    encounter_query: Query<&Transform, (With<EncounterSpawner>, Without<Player>)>,
+    grid: Res<TileGrid>,
     ascii: Res<AsciiSpriteSheet>,
-    time: Res<Time>,
+    movement_mode: Res<MovementMode>,
+    mut play_stats: ResMut<PlayStats>,
+    bonuses: Res<SkillBonuses>,
 ) {
     let (mut player, mut encounter_tracker, player_transform) = player_query.single_mut();
-    let player_translation = player_transform.translation;
+    let player_translation = player_transform.translation.truncate();
+    let last_position = std::mem::replace(&mut encounter_tracker.last_position, player_translation);
 
-    if player.just_moved
-        && encounter_query
-            .iter()
-            .any(|&transform| wall_collision_check(player_translation, transform.translation))
-    {
-        encounter_tracker.timer.tick(time.delta());
+    if !player.just_moved {
+        return;
+    }
+
+    match *movement_mode {
+        //just_moved is only set once a tile step is finished
+        MovementMode::Grid => {}
+        MovementMode::Free => {
+            //capped so a teleport does not count as a long walk
+            encounter_tracker.distance +=
+                Vec2::distance(last_position, player_translation).min(TILE_SIZE);
+            if encounter_tracker.distance < TILE_SIZE {
+                return;
+            }
+            encounter_tracker.distance -= TILE_SIZE;
+        }
+    }
+    play_stats.steps += 1;
+
+    if encounter_tracker.safe_steps > 0 {
+        encounter_tracker.safe_steps -= 1;
+        return;
+    }
+    if encounter_tracker.repel_steps > 0 {
+        encounter_tracker.repel_steps -= 1;
+        return;
+    }
 
-        if encounter_tracker.timer.finished() {
+    //the terrain is the tile under the center of the player
+    if let Some(rate) = grid.encounter_rate(world_to_tile(player_transform.translation)) {
+        if rand::random::<f32>() < rate * bonuses.encounter_multiplier() {
             player.active = false;
             create_fadeout(&mut commands, Some(GameState::Battle), &ascii);
         }
     }
 }
+
+fn start_safe_window(mut tracker_query: Query<&mut EncounterTracker>) {
+    for mut tracker in tracker_query.iter_mut() {
+        tracker.safe_steps = SAFE_STEPS_AFTER_BATTLE;
+    }
+}
+
+fn use_repel(
+    actions: Res<Input<Action>>,
+    mut inventory: ResMut<Inventory>,
+    mut player_query: Query<(&Player, &mut EncounterTracker)>,
+) {
+    if !actions.just_pressed(Action::UseRepel) {
+        return;
+    }
+
+    let (player, mut tracker) = player_query.single_mut();
+    if player.active && inventory.remove(REPEL_ITEM, 1) {
+        tracker.repel_steps += REPEL_STEPS;
+    }
+}
+
 fn camera_follow(
     player_query: Query<&Transform, With<Player>>,
     mut camera_query: Query<&mut Transform, (Without<Player>, With<MainCamera>)>,
@@ -130,70 +240,279 @@ fn camera_follow(
 fn player_movement(
     //used to move the player at a constant speed across different frame rates
     time: Res<Time>,
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
     //a query is a system fn param we use to look up groups of entities. On this situation we want to look up all the entities with the Player component
-    mut player_query: Query<(&mut Player, &mut Transform, &mut PlayerGraphics)>,
+    mut player_query: Query<(
+        &mut Player,
+        &mut Transform,
+        &mut PlayerGraphics,
+        &mut CharacterAnimation,
+    )>,
     //query for tiles with colliders. The Player can have a collision with these tiles, then it could match two queries, it's the reason we are using Without<Player>
     wall_query: Query<&Transform, (With<TileCollider>, Without<Player>)>,
+    grid: Res<TileGrid>,
+    movement_mode: Res<MovementMode>,
+    analog: Res<AnalogMovement>,
 ) {
     //as we have only one player it works fine, but if it returns more than one player, or zero we will have a problem
-    let (mut player, mut transform, mut graphics) = player_query.single_mut();
+    let (mut player, mut transform, mut graphics, mut animation) = player_query.single_mut();
     player.just_moved = false;
 
     if !player.active {
+        animation.play(AnimationState::Idle);
         return;
     }
 
-    let mut y_delta = 0.0;
+    player.running = actions.pressed(Action::Run);
+    let start = transform.translation;
 
-    if keyboard_input.pressed(KeyCode::W) {
-        y_delta += time.delta_seconds() * TILE_SIZE * player.speed;
-    }
-    if keyboard_input.pressed(KeyCode::S) {
-        y_delta -= time.delta_seconds() * TILE_SIZE * player.speed;
+    let facing = if *movement_mode == MovementMode::Grid {
+        grid_movement(
+            &time,
+            &actions,
+            &mut player,
+            &mut transform,
+            graphics.facing,
+            &grid,
+            &wall_query,
+        )
+    } else {
+        free_movement(
+            &time,
+            &actions,
+            &analog,
+            &mut player,
+            &mut transform,
+            graphics.facing,
+            &grid,
+            &wall_query,
+        )
+    };
+    //only written on a turn, the player clips are rebuilt whenever PlayerGraphics changes
+    if graphics.facing != facing {
+        graphics.facing = facing;
     }
 
-    let mut x_delta = 0.0;
-    if keyboard_input.pressed(KeyCode::A) {
-        x_delta -= time.delta_seconds() * TILE_SIZE * player.speed;
+    //walking into a wall without moving counts as standing still, a grid step that just started does not
+    animation.play(if transform.translation == start && !player.mid_step() {
+        AnimationState::Idle
+    } else if player.running {
+        AnimationState::Run
+    } else {
+        AnimationState::Walk
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn free_movement(
+    time: &Time,
+    actions: &Input<Action>,
+    analog: &AnalogMovement,
+    player: &mut Player,
+    transform: &mut Transform,
+    facing: FacingDirection,
+    grid: &TileGrid,
+    wall_query: &Query<&Transform, (With<TileCollider>, Without<Player>)>,
+) -> FacingDirection {
+    let mut facing = facing;
+    let mut direction = Vec2::ZERO;
+    if actions.pressed(Action::MoveUp) {
+        direction.y += 1.0;
+    }
+    if actions.pressed(Action::MoveDown) {
+        direction.y -= 1.0;
     }
-    if keyboard_input.pressed(KeyCode::D) {
-        x_delta += time.delta_seconds() * TILE_SIZE * player.speed;
+    if actions.pressed(Action::MoveLeft) {
+        direction.x -= 1.0;
     }
+    if actions.pressed(Action::MoveRight) {
+        direction.x += 1.0;
+    }
+    //diagonals are as fast as straight lines
+    direction = direction.normalize_or_zero();
 
-    let target = transform.translation + Vec3::new(x_delta, 0.0, 0.0);
+    //a tilted stick replaces the digital directions so walking speed follows the tilt
+    if analog.0 != Vec2::ZERO {
+        direction = analog.0;
+    }
 
-    //fn any with a closure. -> it's like a for looping each tile in the query
-    if !wall_query
-        .iter()
-        .any(|&transform| wall_collision_check(transform.translation, target))
-    {
+    let delta = direction * time.delta_seconds() * TILE_SIZE * player.current_speed();
+    let (x_delta, y_delta) = (delta.x, delta.y);
+
+    //each axis moves on its own, so pushing diagonally into a wall slides along it
+    let target = transform.translation + Vec3::new(x_delta, 0.0, 0.0);
+    if !hits_wall(target, grid, wall_query) {
         if x_delta != 0.0 {
             player.just_moved = true;
             if x_delta > 0.0 {
-                graphics.facing = FacingDirection::Right;
+                facing = FacingDirection::Right;
             } else {
-                graphics.facing = FacingDirection::Left;
+                facing = FacingDirection::Left;
             }
         }
         transform.translation = target;
+    } else if y_delta == 0.0 {
+        if let Some(nudge) = corner_nudge(
+            transform.translation,
+            Vec3::new(x_delta, 0.0, 0.0),
+            Vec3::Y,
+            grid,
+            wall_query,
+        ) {
+            transform.translation += nudge;
+            player.just_moved = true;
+        }
     }
 
     let target = transform.translation + Vec3::new(0.0, y_delta, 0.0);
-    if !wall_query
-        .iter()
-        .any(|&transform| wall_collision_check(transform.translation, target))
-    {
+    if !hits_wall(target, grid, wall_query) {
         if y_delta != 0.0 {
             player.just_moved = true;
             if y_delta > 0.0 {
-                graphics.facing = FacingDirection::Up;
+                facing = FacingDirection::Up;
             } else {
-                graphics.facing = FacingDirection::Down;
+                facing = FacingDirection::Down;
             }
         }
         transform.translation = target;
+    } else if x_delta == 0.0 {
+        if let Some(nudge) = corner_nudge(
+            transform.translation,
+            Vec3::new(0.0, y_delta, 0.0),
+            Vec3::X,
+            grid,
+            wall_query,
+        ) {
+            transform.translation += nudge;
+            player.just_moved = true;
+        }
     }
+    facing
+}
+
+//when walking straight into the edge of a wall, slide sideways around the corner if a small shift would free the way
+fn corner_nudge(
+    translation: Vec3,
+    delta: Vec3,
+    sideways: Vec3,
+    grid: &TileGrid,
+    wall_query: &Query<&Transform, (With<TileCollider>, Without<Player>)>,
+) -> Option<Vec3> {
+    let open_sides: Vec<f32> = [1.0, -1.0]
+        .into_iter()
+        .filter(|side| {
+            let shifted = translation + sideways * *side * CORNER_TOLERANCE;
+            !hits_wall(shifted, grid, wall_query) && !hits_wall(shifted + delta, grid, wall_query)
+        })
+        .collect();
+
+    //with both sides open the player is facing the middle of a wall, not a corner
+    match open_sides.as_slice() {
+        [side] => {
+            let nudge = sideways * *side * delta.length();
+            if hits_wall(translation + nudge, grid, wall_query) {
+                None
+            } else {
+                Some(nudge)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn grid_movement(
+    time: &Time,
+    actions: &Input<Action>,
+    player: &mut Player,
+    transform: &mut Transform,
+    facing: FacingDirection,
+    grid: &TileGrid,
+    wall_query: &Query<&Transform, (With<TileCollider>, Without<Player>)>,
+) -> FacingDirection {
+    if let Some((start, end)) = player.step {
+        player.step_progress += time.delta_seconds() * player.current_speed();
+        if player.step_progress >= 1.0 {
+            transform.translation = end;
+            player.step = None;
+            //only a finished step counts as movement, so encounters and interactions run once per tile
+            player.just_moved = true;
+        } else {
+            transform.translation = start.lerp(end, player.step_progress);
+        }
+        return facing;
+    }
+
+    let (direction, facing) = if actions.pressed(Action::MoveUp) {
+        (Vec3::Y, FacingDirection::Up)
+    } else if actions.pressed(Action::MoveDown) {
+        (-Vec3::Y, FacingDirection::Down)
+    } else if actions.pressed(Action::MoveLeft) {
+        (-Vec3::X, FacingDirection::Left)
+    } else if actions.pressed(Action::MoveRight) {
+        (Vec3::X, FacingDirection::Right)
+    } else {
+        return facing;
+    };
+
+    //snapping also lines the player up again after switching over from free movement
+    let start = transform.translation;
+    let tile = Vec3::new(
+        (start.x / TILE_SIZE).round() * TILE_SIZE,
+        (start.y / TILE_SIZE).round() * TILE_SIZE,
+        start.z,
+    );
+    let end = tile + direction * TILE_SIZE;
+
+    if !hits_wall(end, grid, wall_query) {
+        player.step = Some((start, end));
+        player.step_progress = 0.0;
+    }
+    facing
+}
+
+fn toggle_movement_mode(
+    actions: Res<Input<Action>>,
+    mut movement_mode: ResMut<MovementMode>,
+    mut player_query: Query<(&mut Player, &mut Transform)>,
+) {
+    if !actions.just_pressed(Action::ToggleGrid) {
+        return;
+    }
+
+    *movement_mode = match *movement_mode {
+        MovementMode::Free => MovementMode::Grid,
+        MovementMode::Grid => MovementMode::Free,
+    };
+
+    //finish a step in progress right away instead of leaving the player between tiles
+    if let Ok((mut player, mut transform)) = player_query.get_single_mut() {
+        if let Some((_, end)) = player.step.take() {
+            transform.translation = end;
+        }
+    }
+}
+
+//only the walls on the tiles around the target can touch the player
+fn hits_wall(
+    target: Vec3,
+    grid: &TileGrid,
+    wall_query: &Query<&Transform, (With<TileCollider>, Without<Player>)>,
+) -> bool {
+    let center = world_to_tile(target);
+    let terrain_hit = (-1..=1)
+        .flat_map(|y| (-1..=1).map(move |x| center + IVec2::new(x, y)))
+        .filter(|tile| grid.blocks(*tile))
+        .any(|tile| {
+            let wall = Vec3::new(tile.x as f32 * TILE_SIZE, -(tile.y as f32) * TILE_SIZE, 0.0);
+            wall_collision_check(wall, target)
+        });
+
+    terrain_hit
+        || grid.around(center).any(|tile| {
+            wall_query
+                .get(tile)
+                .map_or(false, |wall| wall_collision_check(wall.translation, target))
+        })
 }
 
 fn wall_collision_check(target_player_pos: Vec3, wall_translation: Vec3) -> bool {
@@ -210,23 +529,43 @@ fn spawn_player(
     //commands as I expect to spawn an entitie
     mut commands: Commands,
     characters: Res<CharacterSheet>,
+    pending_load: Option<Res<PendingLoad>>,
+    classes: Res<Classes>,
+    job: Res<Job>,
 ) {
+    //a new game is moved to the spawn point of the map once it is loaded
+    let mut translation = Vec3::new(0.0, 0.0, PLAYER_Z);
+    let mut exp = 0;
+    let mut level = 1;
+    let mut skill_points = 0;
+    let mut stats = classes.get(&job.class).stats_at(level);
+
+    if let Some(pending_load) = pending_load {
+        let save = &pending_load.0;
+        translation.x = save.position.0;
+        translation.y = save.position.1;
+        exp = save.exp;
+        level = save.level;
+        skill_points = save.skill_points;
+        stats = save.stats.clone();
+        commands.remove_resource::<PendingLoad>();
+    }
+
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite {
-                index: characters.player_down[0],
+                index: characters.player_down[1],
                 custom_size: Some(Vec2::splat(TILE_SIZE)),
                 ..default()
             },
-            transform: Transform::from_xyz(2.0 * TILE_SIZE, -2.0 * TILE_SIZE, 900.0),
+            transform: Transform::from_translation(translation),
             texture_atlas: characters.handle.clone(),
             ..default()
         })
-        .insert(FrameAnimation {
-            timer: Timer::from_seconds(0.2, true),
-            frames: characters.player_down.to_vec(),
-            current_frame: 0,
-        })
+        .insert(FrameAnimation::new(vec![characters.player_down[1]], 0.2))
+        .insert(CharacterAnimation::new(
+            characters.player_clips(FacingDirection::Down),
+        ))
         .insert(PlayerGraphics {
             facing: FacingDirection::Down,
         })
@@ -235,15 +574,18 @@ fn spawn_player(
             speed: 3.0,
             active: true,
             just_moved: false,
-            exp: 0,
-        })
-        .insert(BattleStats {
-            health: 10,
-            max_health: 10,
-            attack: 2,
-            defense: 1,
+            running: false,
+            exp: exp,
+            level: level,
+            skill_points: skill_points,
+            step: None,
+            step_progress: 0.0,
         })
+        .insert(stats)
         .insert(EncounterTracker {
-            timer: Timer::from_seconds(2.0, true),
+            distance: 0.0,
+            last_position: translation.truncate(),
+            safe_steps: 0,
+            repel_steps: 0,
         });
 }
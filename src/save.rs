@@ -0,0 +1,234 @@
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use crate::{
+    battle::BattleStats, character_creation::Hero, classes::Job, graphics::CharacterSheet,
+    inventory::Inventory, play_stats::PlayStats, player::Player, skill_tree::LearnedSkills,
+    story::StoryFlags, tilemap::CurrentMap, GameState,
+};
+
+pub const SAVE_VERSION: u32 = 1;
+pub const SAVE_SLOTS: usize = 3;
+
+pub struct SavePlugin;
+
+//everything needed to rebuild a playthrough, written as RON so it stays human readable
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveData {
+    pub version: u32,
+    #[serde(default)]
+    pub hero: Hero,
+    pub map: String,
+    pub position: (f32, f32),
+    pub stats: BattleStats,
+    pub exp: usize,
+    #[serde(default = "first_level")]
+    pub level: usize,
+    pub inventory: Inventory,
+    pub story: StoryFlags,
+    //saves written before stats were tracked start counting from zero
+    #[serde(default)]
+    pub play_stats: PlayStats,
+    //saves from before classes keep the class picked by default
+    #[serde(default)]
+    pub job: Option<Job>,
+    #[serde(default)]
+    pub skill_points: usize,
+    #[serde(default)]
+    pub skills: LearnedSkills,
+}
+
+fn first_level() -> usize {
+    1
+}
+
+//slot the autosave writes to, chosen when starting or loading a game
+pub struct ActiveSaveSlot(pub usize);
+
+//save picked on the start menu, consumed when the overworld is entered
+pub struct PendingLoad(pub SaveData);
+
+pub struct AutosaveEvent;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ActiveSaveSlot(0))
+            .add_event::<AutosaveEvent>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Overworld)
+                    .with_system(apply_pending_load.label("load")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Battle).with_system(autosave_after_battle),
+            )
+            .add_system(write_autosave);
+    }
+}
+
+fn save_dir() -> PathBuf {
+    ProjectDirs::from("", "", "noob_bevy")
+        .map(|dirs| dirs.data_dir().join("saves"))
+        .unwrap_or_else(|| PathBuf::from("saves"))
+}
+
+fn slot_path(slot: usize) -> PathBuf {
+    save_dir().join(format!("slot_{}.ron", slot))
+}
+
+pub fn slot_exists(slot: usize) -> bool {
+    slot_path(slot).exists()
+}
+
+fn slot_modified(slot: usize) -> Option<SystemTime> {
+    fs::metadata(slot_path(slot))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+//the slot that was written to last, used by "Continue"
+pub fn most_recent_slot() -> Option<usize> {
+    (0..SAVE_SLOTS)
+        .filter_map(|slot| slot_modified(slot).map(|time| (slot, time)))
+        .max_by_key(|(_, time)| *time)
+        .map(|(slot, _)| slot)
+}
+
+//a new game takes the first empty slot, with every slot used the player picks one to overwrite
+pub fn empty_slot() -> Option<usize> {
+    (0..SAVE_SLOTS).find(|slot| !slot_exists(*slot))
+}
+
+pub fn load_slot(slot: usize) -> Option<SaveData> {
+    let path = slot_path(slot);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("Could not read save {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    match ron::from_str::<SaveData>(&contents) {
+        Ok(data) if data.version == SAVE_VERSION => Some(rename_old_maps(data)),
+        Ok(data) => {
+            warn!(
+                "Save {} has version {}, expected {}",
+                path.display(),
+                data.version,
+                SAVE_VERSION
+            );
+            None
+        }
+        Err(err) => {
+            warn!("Could not parse save {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+//maps were plain .txt files before they got their own extension, saves from then point at the old names
+const RENAMED_MAPS: [(&str, &str); 2] =
+    [("map.txt", "world.map.txt"), ("cave.txt", "cave.map.txt")];
+
+fn rename_old_maps(mut data: SaveData) -> SaveData {
+    for (old, new) in RENAMED_MAPS {
+        if data.map == old {
+            data.map = new.to_string();
+        }
+        //opened chests and doors are flagged as "chest:map.txt:3,4"
+        let (old, new) = (format!(":{}:", old), format!(":{}:", new));
+        data.story.flags = std::mem::take(&mut data.story.flags)
+            .into_iter()
+            .map(|flag| flag.replace(&old, &new))
+            .collect();
+    }
+    data
+}
+
+fn write_slot(slot: usize, data: &SaveData) {
+    let path = slot_path(slot);
+    let contents = match ron::ser::to_string_pretty(data, PrettyConfig::default()) {
+        Ok(contents) => contents,
+        Err(err) => {
+            error!("Could not serialize save: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = fs::create_dir_all(save_dir()).and_then(|_| fs::write(&path, contents)) {
+        error!("Could not write save {}: {}", path.display(), err);
+    } else {
+        info!("Saved game to {}", path.display());
+    }
+}
+
+//world resources have to be in place before the map and the player get spawned
+#[allow(clippy::too_many_arguments)]
+fn apply_pending_load(
+    pending: Option<Res<PendingLoad>>,
+    mut current_map: ResMut<CurrentMap>,
+    mut inventory: ResMut<Inventory>,
+    mut story: ResMut<StoryFlags>,
+    mut play_stats: ResMut<PlayStats>,
+    mut hero: ResMut<Hero>,
+    mut characters: ResMut<CharacterSheet>,
+    mut job: ResMut<Job>,
+    mut skills: ResMut<LearnedSkills>,
+) {
+    if let Some(pending) = pending {
+        *hero = pending.0.hero.clone();
+        characters.select_player(hero.appearance);
+        current_map.0 = pending.0.map.clone();
+        *inventory = pending.0.inventory.clone();
+        *story = pending.0.story.clone();
+        *play_stats = pending.0.play_stats.clone();
+        *skills = pending.0.skills.clone();
+        if let Some(saved_job) = &pending.0.job {
+            *job = saved_job.clone();
+        }
+    }
+}
+
+fn autosave_after_battle(mut autosave: EventWriter<AutosaveEvent>) {
+    autosave.send(AutosaveEvent);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_autosave(
+    mut autosave: EventReader<AutosaveEvent>,
+    player_query: Query<(&Player, &BattleStats, &Transform)>,
+    slot: Res<ActiveSaveSlot>,
+    current_map: Res<CurrentMap>,
+    inventory: Res<Inventory>,
+    story: Res<StoryFlags>,
+    play_stats: Res<PlayStats>,
+    hero: Res<Hero>,
+    job: Res<Job>,
+    skills: Res<LearnedSkills>,
+) {
+    if autosave.iter().count() == 0 {
+        return;
+    }
+
+    if let Ok((player, stats, transform)) = player_query.get_single() {
+        let data = SaveData {
+            version: SAVE_VERSION,
+            hero: hero.clone(),
+            map: current_map.0.clone(),
+            position: (transform.translation.x, transform.translation.y),
+            stats: stats.clone(),
+            exp: player.exp,
+            level: player.level,
+            inventory: inventory.clone(),
+            story: story.clone(),
+            play_stats: play_stats.clone(),
+            job: Some(job.clone()),
+            skill_points: player.skill_points,
+            skills: skills.clone(),
+        };
+        write_slot(slot.0, &data);
+    }
+}
@@ -0,0 +1,414 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs};
+
+use crate::{
+    ascii::{spawn_ascii_text, AsciiSpriteSheet, NineSlice, NineSliceIndices},
+    input::{Action, InputDevice, KeyBindings},
+    npc::spawn_text_panel,
+    player::Player,
+    save::AutosaveEvent,
+    GameState, MainCamera, RESOLUTION, TILE_SIZE,
+};
+
+pub struct SkillTreePlugin;
+
+#[derive(Clone, Deserialize)]
+pub enum SkillEffect {
+    //chance out of 1.0 for an attack to deal double damage
+    Crit(f32),
+    //extra exp as a fraction of the reward
+    ExpGain(f32),
+    //change to the chance of random battles, negative makes them rarer
+    EncounterRate(f32),
+    //an attack picked from the battle menu that adds its power to the damage, once per battle
+    Ability(isize),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SkillNode {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub cost: usize,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    //column and row on the skill tree screen
+    pub position: (usize, usize),
+    pub effect: SkillEffect,
+}
+
+//loaded from assets/skill_tree.ron
+#[derive(Default)]
+pub struct SkillTree(pub Vec<SkillNode>);
+
+//what the player picked from the tree, kept in the save
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct LearnedSkills {
+    pub unlocked: BTreeSet<String>,
+    //id of the ability shown in the battle menu
+    pub equipped: Option<String>,
+}
+
+pub struct Ability {
+    pub name: String,
+    pub power: isize,
+}
+
+//passives of every unlocked skill added up, rebuilt whenever something is learned
+#[derive(Default)]
+pub struct SkillBonuses {
+    pub crit_chance: f32,
+    pub exp_gain: f32,
+    pub encounter_rate: f32,
+    pub ability: Option<Ability>,
+}
+
+impl SkillBonuses {
+    pub fn encounter_multiplier(&self) -> f32 {
+        (1.0 + self.encounter_rate).max(0.0)
+    }
+
+    pub fn roll_damage(&self, damage: isize) -> isize {
+        if rand::random::<f32>() < self.crit_chance {
+            damage * 2
+        } else {
+            damage
+        }
+    }
+}
+
+#[derive(Default)]
+struct SkillTreeCursor(usize);
+
+#[derive(Component)]
+struct SkillTreeText;
+
+//border color of a node box, applied once the box is spawned
+#[derive(Component)]
+struct NodeTint(Color);
+
+const COLUMN_SPACING: f32 = 11.0;
+const ROW_SPACING: f32 = 4.0;
+
+impl Plugin for SkillTreePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_skill_tree())
+            .init_resource::<LearnedSkills>()
+            .init_resource::<SkillBonuses>()
+            .init_resource::<SkillTreeCursor>()
+            .add_system(update_skill_bonuses)
+            //after the commands of update are applied, so new boxes never show up blue for a frame
+            .add_system_to_stage(CoreStage::PostUpdate, tint_skill_nodes)
+            .add_system_set(SystemSet::on_update(GameState::Overworld).with_system(open_skill_tree))
+            .add_system_set(
+                SystemSet::on_update(GameState::SkillTree)
+                    .with_system(skill_tree_input.label("skill_input"))
+                    .with_system(draw_skill_tree.after("skill_input")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::SkillTree).with_system(despawn_skill_tree_text),
+            );
+    }
+}
+
+fn load_skill_tree() -> SkillTree {
+    let path = "assets/skill_tree.ron";
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            warn!("Could not read {}: {}", path, err);
+            return SkillTree::default();
+        }
+    };
+    match ron::from_str::<Vec<SkillNode>>(&contents) {
+        Ok(nodes) => SkillTree(nodes),
+        Err(err) => {
+            warn!("Could not parse {}: {}", path, err);
+            SkillTree::default()
+        }
+    }
+}
+
+impl SkillTree {
+    fn get(&self, id: &str) -> Option<&SkillNode> {
+        self.0.iter().find(|node| node.id == id)
+    }
+
+    fn name_of<'a>(&'a self, id: &'a str) -> &'a str {
+        self.get(id).map_or(id, |node| &node.name)
+    }
+
+    //next node in a direction on the screen, the closest one in the same row or column
+    fn neighbour(&self, from: usize, step: IVec2) -> Option<usize> {
+        let (column, row) = self.0[from].position;
+        let (column, row) = (column as i32, row as i32);
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(i, node)| {
+                let offset = IVec2::new(
+                    node.position.0 as i32 - column,
+                    node.position.1 as i32 - row,
+                );
+                let distance = offset.x * step.x + offset.y * step.y;
+                let sideways = offset.x * step.y + offset.y * step.x;
+                (distance > 0 && sideways == 0).then_some((distance, i))
+            })
+            .min()
+            .map(|(_, i)| i)
+    }
+}
+
+impl LearnedSkills {
+    fn can_learn(&self, node: &SkillNode) -> bool {
+        !self.unlocked.contains(&node.id)
+            && node.requires.iter().all(|id| self.unlocked.contains(id))
+    }
+}
+
+fn update_skill_bonuses(
+    learned: Res<LearnedSkills>,
+    tree: Res<SkillTree>,
+    mut bonuses: ResMut<SkillBonuses>,
+) {
+    if !learned.is_changed() {
+        return;
+    }
+    *bonuses = SkillBonuses::default();
+    for node in tree.0.iter() {
+        if !learned.unlocked.contains(&node.id) {
+            continue;
+        }
+        match node.effect {
+            SkillEffect::Crit(chance) => bonuses.crit_chance += chance,
+            SkillEffect::ExpGain(gain) => bonuses.exp_gain += gain,
+            SkillEffect::EncounterRate(change) => bonuses.encounter_rate += change,
+            SkillEffect::Ability(power) => {
+                if learned.equipped.as_ref() == Some(&node.id) {
+                    bonuses.ability = Some(Ability {
+                        name: node.name.clone(),
+                        power,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn open_skill_tree(
+    player_query: Query<&Player>,
+    mut actions: ResMut<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let player = player_query.single();
+    //the tree runs its input in the same frame, the press that opened it would close it again
+    if player.active && actions.just_pressed(Action::Skills) {
+        actions.clear();
+        let _ = state.push(GameState::SkillTree);
+    }
+}
+
+fn skill_tree_input(
+    mut cursor: ResMut<SkillTreeCursor>,
+    mut learned: ResMut<LearnedSkills>,
+    mut player_query: Query<&mut Player>,
+    tree: Res<SkillTree>,
+    mut actions: ResMut<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
+    mut autosave: EventWriter<AutosaveEvent>,
+) {
+    if actions.any_just_pressed([Action::Cancel, Action::Skills]) {
+        actions.clear();
+        let _ = state.pop();
+        return;
+    }
+    if tree.0.is_empty() {
+        return;
+    }
+    if cursor.0 >= tree.0.len() {
+        cursor.0 = 0;
+    }
+
+    let steps = [
+        (Action::MoveUp, IVec2::new(0, -1)),
+        (Action::MoveDown, IVec2::new(0, 1)),
+        (Action::MoveLeft, IVec2::new(-1, 0)),
+        (Action::MoveRight, IVec2::new(1, 0)),
+    ];
+    for (action, step) in steps {
+        if actions.just_pressed(action) {
+            if let Some(next) = tree.neighbour(cursor.0, step) {
+                cursor.0 = next;
+            }
+        }
+    }
+
+    if actions.just_pressed(Action::Confirm) {
+        let node = &tree.0[cursor.0];
+        let mut player = player_query.single_mut();
+        let is_ability = matches!(node.effect, SkillEffect::Ability(_));
+        if learned.can_learn(node) && player.skill_points >= node.cost {
+            player.skill_points -= node.cost;
+            learned.unlocked.insert(node.id.clone());
+            if is_ability && learned.equipped.is_none() {
+                learned.equipped = Some(node.id.clone());
+            }
+            autosave.send(AutosaveEvent);
+        } else if is_ability && learned.unlocked.contains(&node.id) {
+            //learned abilities are swapped in and out of the battle menu
+            learned.equipped = Some(node.id.clone());
+            autosave.send(AutosaveEvent);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_skill_tree(
+    mut commands: Commands,
+    cursor: Res<SkillTreeCursor>,
+    learned: Res<LearnedSkills>,
+    tree: Res<SkillTree>,
+    player_query: Query<&Player>,
+    bindings: Res<KeyBindings>,
+    device: Res<InputDevice>,
+    text_query: Query<Entity, With<SkillTreeText>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    ascii: Res<AsciiSpriteSheet>,
+    indices: Res<NineSliceIndices>,
+) {
+    if !text_query.is_empty()
+        && !cursor.is_changed()
+        && !learned.is_changed()
+        && !device.is_changed()
+    {
+        return;
+    }
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let player = player_query.single();
+    let camera = camera_query.single().translation;
+    let left = camera.x - RESOLUTION + TILE_SIZE;
+    let top = camera.y + 1.0 - TILE_SIZE;
+    let node_center = |(column, row): (usize, usize)| {
+        Vec2::new(
+            left + (column as f32 * COLUMN_SPACING + COLUMN_SPACING / 2.0) * TILE_SIZE,
+            top - (row as f32 * ROW_SPACING + 3.0) * TILE_SIZE,
+        )
+    };
+
+    let spawn_line = |commands: &mut Commands, line: &str, translation: Vec3| {
+        let text = spawn_ascii_text(commands, &ascii, line, translation);
+        commands.entity(text).insert(SkillTreeText);
+    };
+
+    spawn_line(
+        &mut commands,
+        &format!("Skills{:>20}", format!("Points {}", player.skill_points)),
+        Vec3::new(left, top, 900.0),
+    );
+
+    for (i, node) in tree.0.iter().enumerate() {
+        let center = node_center(node.position);
+        //unlocked skills get a star, the ability in the battle menu an arrow
+        let marker = if learned.equipped.as_ref() == Some(&node.id) {
+            '>'
+        } else if learned.unlocked.contains(&node.id) {
+            '*'
+        } else {
+            ' '
+        };
+        let text = format!("{}{}", marker, node.name);
+        let panel = spawn_text_panel(&mut commands, &ascii, &indices, center, &text);
+        let tint = if i == cursor.0 {
+            Color::RED
+        } else if learned.unlocked.contains(&node.id) {
+            Color::rgb(0.3, 0.9, 0.3)
+        } else if learned.can_learn(node) {
+            Color::rgb(0.3, 0.3, 0.9)
+        } else {
+            Color::rgb(0.4, 0.4, 0.4)
+        };
+        commands
+            .entity(panel)
+            .insert(NodeTint(tint))
+            .insert(SkillTreeText);
+
+        //boxes stacked in a column are linked, requirements from other columns are listed below
+        for required in node.requires.iter().filter_map(|id| tree.get(id)) {
+            if required.position.0 == node.position.0 && required.position.1 < node.position.1 {
+                let parent = node_center(required.position);
+                let mut y = parent.y - 2.0 * TILE_SIZE;
+                while y > center.y + 1.5 * TILE_SIZE {
+                    spawn_line(&mut commands, "|", Vec3::new(center.x, y, 900.0));
+                    y -= TILE_SIZE;
+                }
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    if let Some(node) = tree.0.get(cursor.0) {
+        lines.push(format!("{}: {}", node.name, node.description));
+        let mut details = format!("Cost {}", node.cost);
+        if !node.requires.is_empty() {
+            let requires = node
+                .requires
+                .iter()
+                .map(|id| tree.name_of(id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            details.push_str(&format!("  Needs {}", requires));
+        }
+        lines.push(details);
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "{} learn {} back",
+        bindings.prompt(Action::Confirm, *device),
+        bindings.prompt(Action::Cancel, *device)
+    ));
+
+    let rows = tree
+        .0
+        .iter()
+        .map(|node| node.position.1 + 1)
+        .max()
+        .unwrap_or(0);
+    let first_line = (rows as f32 * ROW_SPACING + 2.0) * TILE_SIZE;
+    for (i, line) in lines.iter().enumerate() {
+        spawn_line(
+            &mut commands,
+            line,
+            Vec3::new(left, top - first_line - i as f32 * TILE_SIZE, 900.0),
+        );
+    }
+}
+
+//text panels are always blue, node boxes are recolored to show what can be learned
+fn tint_skill_nodes(
+    node_query: Query<(&NodeTint, &Children), Added<NodeTint>>,
+    nine_slice_query: Query<&Children, With<NineSlice>>,
+    mut sprites_query: Query<&mut TextureAtlasSprite>,
+) {
+    for (tint, children) in node_query.iter() {
+        for nine_slice in children
+            .iter()
+            .filter_map(|child| nine_slice_query.get(*child).ok())
+        {
+            for child in nine_slice.iter() {
+                if let Ok(mut sprite) = sprites_query.get_mut(*child) {
+                    sprite.color = tint.0;
+                }
+            }
+        }
+    }
+}
+
+fn despawn_skill_tree_text(mut commands: Commands, text_query: Query<Entity, With<SkillTreeText>>) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,12 +1,45 @@
 use bevy::{prelude::*, ui::FocusPolicy};
 
-use crate::{ascii::AsciiSpriteSheet, fadeout::create_fadeout, GameState};
+use crate::{
+    ascii::AsciiSpriteSheet,
+    fadeout::create_fadeout,
+    input::Action,
+    save::{
+        empty_slot, load_slot, most_recent_slot, slot_exists, ActiveSaveSlot, PendingLoad,
+        SAVE_SLOTS,
+    },
+    GameState,
+};
 
 pub struct MainMenuPlugin;
 
 #[derive(Component)]
 pub struct ButtonActive(bool);
 
+#[derive(Component, Clone, Copy)]
+pub enum MenuButton {
+    StartGame,
+    Continue,
+    Load,
+    Slot(usize),
+    Overwrite(usize),
+    Back,
+}
+
+#[derive(Component)]
+struct MenuRoot;
+
+//index of the button picked with the keyboard or gamepad, mouse hovering moves it too
+#[derive(Default)]
+struct MenuSelection(usize);
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum MenuPage {
+    Main,
+    Load,
+    Overwrite,
+}
+
 struct UiAssets {
     font: Handle<Font>,
     button: Handle<Image>,
@@ -15,29 +48,50 @@ struct UiAssets {
 
 impl Plugin for MainMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup_menu)
+        app.insert_resource(MenuPage::Main)
+            .init_resource::<MenuSelection>()
+            .add_startup_system(setup_menu)
             .add_system_set(SystemSet::on_pause(GameState::StartMenu).with_system(despawn_menu))
-            .add_system(handle_start_button);
+            .add_system(handle_start_button)
+            .add_system(spawn_menu_page)
+            .add_system(highlight_menu_selection);
     }
 }
 
-fn despawn_menu(mut commands: Commands, button_query: Query<Entity, With<Button>>) {
-    for ent in button_query.iter() {
+fn despawn_menu(mut commands: Commands, root_query: Query<Entity, With<MenuRoot>>) {
+    for ent in root_query.iter() {
         commands.entity(ent).despawn_recursive();
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_start_button(
     mut commands: Commands,
-    mut interaction_query: Query<
-        (&Children, &mut ButtonActive, &Interaction),
+    interaction_query: Query<
+        (Entity, &Children, &ButtonActive, &MenuButton, &Interaction),
         Changed<Interaction>,
     >,
+    button_query: Query<(&ButtonActive, &MenuButton)>,
+    root_query: Query<&Children, With<MenuRoot>>,
     mut image_query: Query<&mut UiImage>,
+    mut selection: ResMut<MenuSelection>,
+    mut page: ResMut<MenuPage>,
+    mut active_slot: ResMut<ActiveSaveSlot>,
+    //once a game is starting every other click is ignored until the fade hides the menu
+    mut starting: Local<bool>,
+    actions: Res<Input<Action>>,
+    mut state: ResMut<State<GameState>>,
     ui_assets: Res<UiAssets>,
     ascii: Res<AsciiSpriteSheet>,
 ) {
-    for (children, mut active, interaction) in interaction_query.iter_mut() {
+    //buttons are listed in the order they were spawned under the menu root
+    let buttons: Vec<Entity> = root_query
+        .get_single()
+        .map(|children| children.iter().copied().collect())
+        .unwrap_or_default();
+    let mut pressed = None;
+
+    for (entity, children, active, button, interaction) in interaction_query.iter() {
         let child = children.iter().next().unwrap();
         let mut image = image_query.get_mut(*child).unwrap();
 
@@ -45,15 +99,118 @@ fn handle_start_button(
             Interaction::Clicked => {
                 if active.0 {
                     image.0 = ui_assets.button_pressed.clone();
-                    create_fadeout(&mut commands, Some(GameState::Overworld), &ascii);
-                    active.0 = false;
+                    pressed = Some(*button);
+                }
+            }
+            Interaction::Hovered => {
+                image.0 = ui_assets.button.clone();
+                if let Some(index) = buttons.iter().position(|button| *button == entity) {
+                    selection.0 = index;
                 }
             }
-            Interaction::Hovered | Interaction::None => {
+            Interaction::None => {
                 image.0 = ui_assets.button.clone();
             }
         }
     }
+
+    //keyboard and gamepad drive the menu only while it is the state on screen
+    if state.current() == &GameState::StartMenu && !buttons.is_empty() {
+        let count = buttons.len();
+        if actions.just_pressed(Action::MoveUp) {
+            selection.0 = (selection.0 + count - 1) % count;
+        }
+        if actions.just_pressed(Action::MoveDown) {
+            selection.0 = (selection.0 + 1) % count;
+        }
+        if actions.just_pressed(Action::Confirm) {
+            if let Some(Ok((active, button))) = buttons
+                .get(selection.0)
+                .map(|entity| button_query.get(*entity))
+            {
+                if active.0 {
+                    pressed = Some(*button);
+                }
+            }
+        }
+    }
+
+    let button = match pressed {
+        Some(button) if !*starting => button,
+        _ => return,
+    };
+
+    let new_game_slot = match button {
+        MenuButton::StartGame => empty_slot(),
+        MenuButton::Overwrite(slot) => Some(slot),
+        _ => None,
+    };
+    //a new game goes through character creation, which does the fade itself
+    if let Some(slot) = new_game_slot {
+        active_slot.0 = slot;
+        *starting = true;
+        let _ = state.push(GameState::CharacterCreation);
+        return;
+    }
+
+    let slot_to_load = match button {
+        //every slot is used, ask which save the new game replaces instead of picking one
+        MenuButton::StartGame | MenuButton::Overwrite(_) => {
+            *page = MenuPage::Overwrite;
+            None
+        }
+        MenuButton::Continue => most_recent_slot(),
+        MenuButton::Slot(slot) => Some(slot),
+        MenuButton::Load => {
+            *page = MenuPage::Load;
+            None
+        }
+        MenuButton::Back => {
+            *page = MenuPage::Main;
+            None
+        }
+    };
+
+    if let Some(slot) = slot_to_load {
+        if let Some(save) = load_slot(slot) {
+            active_slot.0 = slot;
+            commands.insert_resource(PendingLoad(save));
+            *starting = true;
+        }
+    }
+
+    if *starting {
+        create_fadeout(&mut commands, Some(GameState::Overworld), &ascii);
+    }
+}
+
+//tints the selected button so keyboard and gamepad players can see where they are
+fn highlight_menu_selection(
+    selection: Res<MenuSelection>,
+    root_query: Query<&Children, With<MenuRoot>>,
+    button_query: Query<&Children, With<MenuButton>>,
+    mut color_query: Query<&mut UiColor, With<UiImage>>,
+) {
+    let buttons = match root_query.get_single() {
+        Ok(buttons) => buttons,
+        Err(_) => return,
+    };
+
+    for (index, button) in buttons.iter().enumerate() {
+        let image = match button_query.get(*button).map(|children| children.first()) {
+            Ok(Some(image)) => *image,
+            _ => continue,
+        };
+        if let Ok(mut color) = color_query.get_mut(image) {
+            //keep the alpha, the fadeout is in charge of it
+            let alpha = color.0.a();
+            color.0 = if index == selection.0 {
+                Color::rgba(1.0, 0.85, 0.5, alpha)
+            } else {
+                Color::rgba(1.0, 1.0, 1.0, alpha)
+            };
+        }
+    }
 }
 
 fn setup_menu(mut commands: Commands, assets: Res<AssetServer>) {
@@ -65,19 +222,109 @@ fn setup_menu(mut commands: Commands, assets: Res<AssetServer>) {
 
     //commands.spawn_bundle(Camera2dBundle::default());
     commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                //bevy ui has y pointing up, reversing the column lists the buttons from the top
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .insert(MenuRoot)
+        .insert(Name::new("Main Menu"));
+    commands.insert_resource(ui_assets);
+}
+
+//rebuilds the buttons under the menu root every time the page changes
+fn spawn_menu_page(
+    mut commands: Commands,
+    page: Res<MenuPage>,
+    root_query: Query<Entity, With<MenuRoot>>,
+    mut selection: ResMut<MenuSelection>,
+    ui_assets: Res<UiAssets>,
+) {
+    if !page.is_changed() {
+        return;
+    }
+    selection.0 = 0;
+    let root = match root_query.get_single() {
+        Ok(root) => root,
+        Err(_) => return,
+    };
+
+    commands.entity(root).despawn_descendants();
+    commands.entity(root).with_children(|parent| match *page {
+        MenuPage::Main => {
+            let has_save = most_recent_slot().is_some();
+            spawn_menu_button(
+                parent,
+                &ui_assets,
+                "Start Game",
+                MenuButton::StartGame,
+                true,
+            );
+            spawn_menu_button(
+                parent,
+                &ui_assets,
+                "Continue",
+                MenuButton::Continue,
+                has_save,
+            );
+            spawn_menu_button(parent, &ui_assets, "Load", MenuButton::Load, has_save);
+        }
+        MenuPage::Load => {
+            for slot in 0..SAVE_SLOTS {
+                let exists = slot_exists(slot);
+                let text = if exists {
+                    format!("Slot {}", slot + 1)
+                } else {
+                    format!("Slot {} - Empty", slot + 1)
+                };
+                spawn_menu_button(parent, &ui_assets, &text, MenuButton::Slot(slot), exists);
+            }
+            spawn_menu_button(parent, &ui_assets, "Back", MenuButton::Back, true);
+        }
+        MenuPage::Overwrite => {
+            for slot in 0..SAVE_SLOTS {
+                let text = format!("Overwrite {}", slot + 1);
+                spawn_menu_button(parent, &ui_assets, &text, MenuButton::Overwrite(slot), true);
+            }
+            spawn_menu_button(parent, &ui_assets, "Back", MenuButton::Back, true);
+        }
+    });
+}
+
+fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    ui_assets: &UiAssets,
+    text: &str,
+    id: MenuButton,
+    active: bool,
+) {
+    let text_color = if active {
+        Color::rgb(0.9, 0.9, 0.9)
+    } else {
+        Color::rgb(0.5, 0.5, 0.5)
+    };
+
+    parent
         .spawn_bundle(ButtonBundle {
             style: Style {
-                align_self: AlignSelf::Center,
                 align_items: AlignItems::Center,
                 justify_content: JustifyContent::Center,
                 size: Size::new(Val::Percent(20.0), Val::Percent(10.0)),
-                margin: UiRect::all(Val::Auto),
+                margin: UiRect::all(Val::Px(10.0)),
                 ..Default::default()
             },
             color: Color::NONE.into(),
             ..Default::default()
         })
-        .insert(ButtonActive(true))
+        .insert(ButtonActive(active))
+        .insert(id)
         .with_children(|parent| {
             parent
                 .spawn_bundle(ImageBundle {
@@ -94,11 +341,11 @@ fn setup_menu(mut commands: Commands, assets: Res<AssetServer>) {
                 .with_children(|parent| {
                     parent.spawn_bundle(TextBundle {
                         text: Text::from_section(
-                            "Start Game",
+                            text,
                             TextStyle {
                                 font: ui_assets.font.clone(),
                                 font_size: 40.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
+                                color: text_color,
                             },
                             //Default::default(),
                         ),
@@ -107,5 +354,4 @@ fn setup_menu(mut commands: Commands, assets: Res<AssetServer>) {
                     });
                 });
         });
-    commands.insert_resource(ui_assets);
 }
@@ -0,0 +1,28 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+pub struct StoryPlugin;
+
+//everything that happened in the world and should not happen twice, saved alongside the player
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct StoryFlags {
+    pub flags: BTreeSet<String>,
+    pub defeated_bosses: BTreeSet<String>,
+}
+
+impl StoryFlags {
+    pub fn set(&mut self, flag: &str) {
+        self.flags.insert(flag.to_string());
+    }
+
+    pub fn is_set(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+}
+
+impl Plugin for StoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StoryFlags>();
+    }
+}
@@ -0,0 +1,321 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{Indices, MeshVertexBufferLayout, PrimitiveTopology, VertexAttributeValues},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+    },
+    sprite::{Material2d, Material2dKey, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle},
+    utils::HashMap,
+};
+
+use crate::{ascii::AsciiSpriteSheet, graphics::FrameAnimation, tilemap::TileAnimation, TILE_SIZE};
+
+//tiles nothing interacts with are drawn as one mesh per CHUNK_SIZE x CHUNK_SIZE block instead of one sprite each,
+//a 256x256 map is 256 entities instead of 65536 and chunks outside the camera are culled by their aabb
+//animated tiles stay in the mesh too, only their vertices are rewritten
+pub struct TileChunkPlugin;
+
+pub const CHUNK_SIZE: i32 = 16;
+
+//the ascii atlas with the color of every tile multiplied in, like the color of a TextureAtlasSprite
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "8d2f4a61-93c5-4b7e-a0d1-6c2e5f9b3a47"]
+pub struct TileChunkMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub texture: Handle<Image>,
+}
+
+impl Material2d for TileChunkMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/tile_chunk.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/tile_chunk.wgsl".into()
+    }
+
+    //the default 2d mesh layout has no vertex colors
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(2),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
+
+//every chunk shares the same material, only the meshes differ
+pub struct AsciiChunkMaterial(pub Handle<TileChunkMaterial>);
+
+#[derive(Component)]
+pub struct TileChunk;
+
+//one glyph of a chunk, the tile is the column and row in the map
+//animation points into the animations handed to spawn_tile_chunks
+#[derive(Clone, Copy)]
+pub struct ChunkTile {
+    pub tile: IVec2,
+    pub index: usize,
+    pub color: Color,
+    pub animation: Option<usize>,
+}
+
+enum ChunkAnimation {
+    Frames(FrameAnimation),
+    Pulse { color: Color, period: f32 },
+}
+
+impl ChunkAnimation {
+    fn new(animation: &TileAnimation) -> Self {
+        match animation {
+            TileAnimation::Glyphs { glyphs, frame_time } => {
+                ChunkAnimation::Frames(FrameAnimation::new(
+                    glyphs.iter().map(|glyph| *glyph as usize).collect(),
+                    *frame_time,
+                ))
+            }
+            TileAnimation::Pulse {
+                color: (r, g, b),
+                period,
+            } => ChunkAnimation::Pulse {
+                color: Color::rgb(*r, *g, *b),
+                period: *period,
+            },
+        }
+    }
+
+    fn cycle_time(&self) -> f32 {
+        match self {
+            ChunkAnimation::Frames(frames) => frames.cycle_time(),
+            ChunkAnimation::Pulse { period, .. } => *period,
+        }
+    }
+}
+
+//a tile of the chunk that animates, found by the first of its four vertices
+struct AnimatedTile {
+    vertex: usize,
+    animation: usize,
+    color: Color,
+    //seconds ahead of the other tiles with the same animation
+    phase: f32,
+}
+
+//the animated tiles of a chunk are updated in its mesh, there is no entity per tile
+#[derive(Component)]
+struct AnimatedChunk {
+    tiles: Vec<AnimatedTile>,
+    animations: Vec<ChunkAnimation>,
+}
+
+impl Plugin for TileChunkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(Material2dPlugin::<TileChunkMaterial>::default())
+            .add_startup_system(create_chunk_material)
+            .add_system(animate_tile_chunks);
+    }
+}
+
+//runs after the ascii sheet is created in PreStartup
+fn create_chunk_material(
+    mut commands: Commands,
+    ascii: Res<AsciiSpriteSheet>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut materials: ResMut<Assets<TileChunkMaterial>>,
+) {
+    let atlas = atlases
+        .get(&ascii.0)
+        .expect("ascii atlas is added at startup");
+    let material = materials.add(TileChunkMaterial {
+        texture: atlas.texture.clone(),
+    });
+    commands.insert_resource(AsciiChunkMaterial(material));
+}
+
+//top left tile of the chunk the tile belongs to
+pub fn chunk_origin(tile: IVec2) -> IVec2 {
+    IVec2::new(tile.x.div_euclid(CHUNK_SIZE), tile.y.div_euclid(CHUNK_SIZE)) * CHUNK_SIZE
+}
+
+//a quad per tile, placed relative to the top left tile of the chunk
+pub fn build_chunk_mesh(origin: IVec2, tiles: &[ChunkTile], atlas: &TextureAtlas) -> Mesh {
+    let mut positions = Vec::with_capacity(tiles.len() * 4);
+    let mut uvs = Vec::with_capacity(tiles.len() * 4);
+    let mut colors = Vec::with_capacity(tiles.len() * 4);
+    let mut indices = Vec::with_capacity(tiles.len() * 6);
+
+    let half = TILE_SIZE / 2.0;
+    for tile in tiles.iter() {
+        let local = tile.tile - origin;
+        let center = Vec2::new(local.x as f32 * TILE_SIZE, -(local.y as f32) * TILE_SIZE);
+
+        let first = positions.len() as u32;
+        positions.extend([
+            [center.x - half, center.y + half, 0.0],
+            [center.x + half, center.y + half, 0.0],
+            [center.x + half, center.y - half, 0.0],
+            [center.x - half, center.y - half, 0.0],
+        ]);
+        uvs.extend(glyph_uvs(atlas, tile.index));
+        colors.extend([tile.color.as_linear_rgba_f32(); 4]);
+        indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+//texture rows grow downwards like map rows, so the top of the quad takes the top of the glyph
+fn glyph_uvs(atlas: &TextureAtlas, index: usize) -> [[f32; 2]; 4] {
+    let rect = atlas.textures[index];
+    let (min, max) = (rect.min / atlas.size, rect.max / atlas.size);
+    [
+        [min.x, min.y],
+        [max.x, min.y],
+        [max.x, max.y],
+        [min.x, max.y],
+    ]
+}
+
+//the same tile always gets the same phase, somewhere between 0 and 1
+fn tile_phase(tile: IVec2) -> f32 {
+    let hash = (tile.x as u32).wrapping_mul(73_856_093) ^ (tile.y as u32).wrapping_mul(19_349_663);
+    (hash % 1000) as f32 / 1000.0
+}
+
+//groups the tiles into chunks and spawns one mesh entity for each, all at the given z
+pub fn spawn_tile_chunks(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: &AsciiChunkMaterial,
+    atlas: &TextureAtlas,
+    tiles: &[ChunkTile],
+    animations: &[TileAnimation],
+    z: f32,
+) -> Vec<Entity> {
+    let mut chunks: HashMap<IVec2, Vec<ChunkTile>> = HashMap::default();
+    for tile in tiles.iter() {
+        chunks
+            .entry(chunk_origin(tile.tile))
+            .or_default()
+            .push(*tile);
+    }
+
+    chunks
+        .into_iter()
+        .map(|(origin, tiles)| {
+            let mesh = build_chunk_mesh(origin, &tiles, atlas);
+            //without an aabb the chunk would be drawn even when it is far off screen
+            let aabb = mesh.compute_aabb();
+            let translation = Vec3::new(
+                origin.x as f32 * TILE_SIZE,
+                -(origin.y as f32) * TILE_SIZE,
+                z,
+            );
+            let mut chunk = commands.spawn_bundle(MaterialMesh2dBundle {
+                mesh: Mesh2dHandle(meshes.add(mesh)),
+                material: material.0.clone(),
+                transform: Transform::from_translation(translation),
+                ..Default::default()
+            });
+            chunk
+                .insert(TileChunk)
+                .insert(Name::new(format!("Chunk {} {}", origin.x, origin.y)));
+            if let Some(aabb) = aabb {
+                chunk.insert(aabb);
+            }
+
+            if tiles.iter().all(|tile| tile.animation.is_none()) {
+                return chunk.id();
+            }
+            let animations: Vec<ChunkAnimation> =
+                animations.iter().map(ChunkAnimation::new).collect();
+            let animated = tiles
+                .iter()
+                .enumerate()
+                .filter_map(|(i, tile)| {
+                    let animation = tile.animation?;
+                    Some(AnimatedTile {
+                        vertex: i * 4,
+                        animation,
+                        color: tile.color,
+                        phase: tile_phase(tile.tile) * animations[animation].cycle_time(),
+                    })
+                })
+                .collect();
+            chunk.insert(AnimatedChunk {
+                tiles: animated,
+                animations,
+            });
+            chunk.id()
+        })
+        .collect()
+}
+
+//rewrites the glyphs and colors of the animated tiles in the chunk meshes
+fn animate_tile_chunks(
+    chunk_query: Query<(&Mesh2dHandle, &AnimatedChunk, &ComputedVisibility)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    ascii: Res<AsciiSpriteSheet>,
+    atlases: Res<Assets<TextureAtlas>>,
+    time: Res<Time>,
+) {
+    let atlas = match atlases.get(&ascii.0) {
+        Some(atlas) => atlas,
+        None => return,
+    };
+    let seconds = time.seconds_since_startup() as f32;
+
+    for (mesh, animated, visibility) in chunk_query.iter() {
+        //culled or hidden chunks are not drawn, they pick up the right frame once they are
+        if !visibility.is_visible() {
+            continue;
+        }
+        let mesh = match meshes.get_mut(&mesh.0) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+
+        if let Some(VertexAttributeValues::Float32x2(uvs)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+        {
+            for tile in animated.tiles.iter() {
+                if let ChunkAnimation::Frames(frames) = &animated.animations[tile.animation] {
+                    let index = frames.frame_at(seconds + tile.phase);
+                    uvs[tile.vertex..tile.vertex + 4].copy_from_slice(&glyph_uvs(atlas, index));
+                }
+            }
+        }
+
+        if let Some(VertexAttributeValues::Float32x4(colors)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR)
+        {
+            for tile in animated.tiles.iter() {
+                if let ChunkAnimation::Pulse { color, period } =
+                    &animated.animations[tile.animation]
+                {
+                    //0 at the base color, 1 at the pulse color, smooth at both ends
+                    let progress = (seconds + tile.phase) / period * std::f32::consts::TAU;
+                    let amount = (1.0 - progress.cos()) / 2.0;
+                    let from = Vec4::from(tile.color.as_linear_rgba_f32());
+                    let to = Vec4::from(color.as_linear_rgba_f32());
+                    colors[tile.vertex..tile.vertex + 4].fill(from.lerp(to, amount).to_array());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,626 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::{
+    battle::EnemyType,
+    npc::Npc,
+    tilemap::{
+        parse_color, validate_map, LayerKind, MapAsset, MapError, MapLayer, MapMetadata,
+        TileAnimation, TileDef, TileTrigger, EMPTY_GLYPH,
+    },
+};
+
+//loads maps saved from Tiled as JSON (*.tmj) into the same MapAsset the text maps become
+//
+//tiles of the embedded tileset describe themselves with custom properties:
+//  glyph (string, required), color (color), collider (bool), encounter (float), npc (string), fade (bool),
+//  pulse (color) with pulse_period (float, seconds)
+//tiles animated in the tileset editor cycle through the glyphs of their frames
+//objects mark single tiles, picked by their type (class in newer Tiled versions):
+//  spawn (where a new game starts, every map needs one), entry (named after the object), warp (map and entry properties), sign (text),
+//  door, chest (items like "Repel:3, Potion"), npc (npc)
+//the enemies map property lists who shows up in random battles, like "Bat:3, Ghost"
+//any other property of a tile or an object is kept in the properties of the tile, those of the map in the map properties
+//tile layers have to use the CSV layer format, later layers cover earlier ones
+//layers named "objects" and "overhead" become those map layers, every other one is ground
+pub struct TiledPlugin;
+
+impl Plugin for TiledPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset_loader::<TiledMapLoader>();
+    }
+}
+
+#[derive(Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TiledLayer>,
+    tilesets: Vec<TiledTileset>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        data: Vec<u32>,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        #[serde(default)]
+        objects: Vec<TiledObject>,
+    },
+    //image layers and groups have nothing we can use
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    firstgid: u32,
+    //set when the tileset lives in its own file
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+    #[serde(default)]
+    animation: Vec<TiledFrame>,
+}
+
+#[derive(Deserialize)]
+struct TiledFrame {
+    tileid: u32,
+    //milliseconds
+    duration: u32,
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: Value,
+}
+
+//the top bits of a tile id say how the tile is flipped, which ascii tiles do not care about
+const GID_MASK: u32 = 0x1FFF_FFFF;
+//legend keys of imported maps are never typed by hand, so they come from the private use area
+const FIRST_LEGEND_KEY: u32 = 0xE000;
+
+#[derive(Default)]
+struct TiledMapLoader;
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let map = import_tiled(bytes).map_err(|message| MapError::new(&path, message))?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    //only the extension Tiled gives its JSON maps, other JSON files are not maps
+    fn extensions(&self) -> &[&str] {
+        &["tmj"]
+    }
+}
+
+fn property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a Value> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+fn string_property(properties: &[TiledProperty], name: &str) -> Option<String> {
+    property(properties, name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+//numbers and bools are kept as they are written in the json
+fn property_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+//the properties the importer did not turn into something of its own
+fn leftover_properties<'a>(
+    properties: &'a [TiledProperty],
+    known: &'a [&'a str],
+) -> impl Iterator<Item = (String, String)> + 'a {
+    properties
+        .iter()
+        .filter(move |property| !known.contains(&property.name.as_str()))
+        .map(|property| (property.name.clone(), property_text(&property.value)))
+}
+
+fn parse_npc(value: &str) -> Result<Npc, String> {
+    serde_json::from_value(Value::String(value.to_string()))
+        .map_err(|_| format!("unknown npc {}", value))
+}
+
+fn parse_enemy(value: &str) -> Result<EnemyType, String> {
+    serde_json::from_value(Value::String(value.to_string()))
+        .map_err(|_| format!("unknown enemy {}", value))
+}
+
+//"Repel:3, Potion" becomes three repels and a potion
+fn parse_items(text: &str) -> Result<Vec<(String, usize)>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.split_once(':') {
+            Some((name, amount)) => amount
+                .trim()
+                .parse()
+                .map(|amount| (name.trim().to_string(), amount))
+                .map_err(|_| format!("bad item amount in {}", item)),
+            None => Ok((item.to_string(), 1)),
+        })
+        .collect()
+}
+
+//tile properties the importer turns into fields of the TileDef
+const TILE_PROPERTIES: [&str; 8] = [
+    "glyph",
+    "color",
+    "collider",
+    "encounter",
+    "npc",
+    "fade",
+    "pulse",
+    "pulse_period",
+];
+
+fn tile_def(gid: u32, properties: &[TiledProperty]) -> Result<TileDef, String> {
+    let glyph = string_property(properties, "glyph")
+        .and_then(|glyph| glyph.chars().next())
+        .ok_or_else(|| format!("tile {} has no glyph property", gid))?;
+    if glyph as u32 >= 256 {
+        return Err(format!(
+            "tile {} uses {:?}, Ascii.png only has 256 glyphs",
+            gid, glyph
+        ));
+    }
+    let mut def = TileDef {
+        glyph: Some(glyph),
+        collider: property(properties, "collider")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        encounter: property(properties, "encounter")
+            .and_then(Value::as_f64)
+            .map(|rate| rate as f32),
+        fade: property(properties, "fade")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        properties: leftover_properties(properties, &TILE_PROPERTIES).collect(),
+        ..Default::default()
+    };
+    if let Some(color) = string_property(properties, "color") {
+        def.color = parse_color(&color)?;
+    }
+    if let Some(npc) = string_property(properties, "npc") {
+        def.npc = Some(parse_npc(&npc)?);
+    }
+    if let Some(color) = string_property(properties, "pulse") {
+        def.animation = Some(TileAnimation::Pulse {
+            color: parse_color(&color)?,
+            period: property(properties, "pulse_period")
+                .and_then(Value::as_f64)
+                .unwrap_or(1.0) as f32,
+        });
+    }
+    Ok(def)
+}
+
+//what the loader uses, a map that reads fine and passes the checks of validate_map
+pub fn import_tiled(bytes: &[u8]) -> Result<MapAsset, String> {
+    let map = read_tiled(bytes)?;
+    validate_map(&map)?;
+    Ok(map)
+}
+
+//only turns the json into a map, tools that report every problem at once check it themselves
+pub fn read_tiled(bytes: &[u8]) -> Result<MapAsset, String> {
+    let tiled: TiledMap =
+        serde_json::from_slice(bytes).map_err(|err| format!("bad Tiled json: {}", err))?;
+    if tiled.infinite {
+        return Err("infinite Tiled maps are not supported".to_string());
+    }
+
+    let mut defs = BTreeMap::new();
+    for tileset in tiled.tilesets.iter() {
+        if let Some(source) = &tileset.source {
+            return Err(format!(
+                "tileset {} is external, embed it in the map",
+                source
+            ));
+        }
+        for tile in tileset.tiles.iter() {
+            let gid = tileset.firstgid + tile.id;
+            defs.insert(gid, tile_def(gid, &tile.properties)?);
+        }
+    }
+
+    //frames are other tiles of the same tileset, so every def has to exist first
+    for tileset in tiled.tilesets.iter() {
+        for tile in tileset
+            .tiles
+            .iter()
+            .filter(|tile| !tile.animation.is_empty())
+        {
+            let gid = tileset.firstgid + tile.id;
+            let glyphs = tile
+                .animation
+                .iter()
+                .map(|frame| {
+                    defs.get(&(tileset.firstgid + frame.tileid))
+                        .and_then(|def| def.glyph)
+                        .ok_or_else(|| {
+                            format!("animation of tile {} uses a tile without a glyph", gid)
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            //one frame time for all frames, the glyphs follow each other evenly
+            let total: u32 = tile.animation.iter().map(|frame| frame.duration).sum();
+            let frame_time = total as f32 / tile.animation.len() as f32 / 1000.0;
+            if let Some(def) = defs.get_mut(&gid) {
+                def.animation = Some(TileAnimation::Glyphs { glyphs, frame_time });
+            }
+        }
+    }
+
+    //the tile layers of each kind stacked into one grid of tile ids, 0 is an empty cell
+    let kinds = [LayerKind::Ground, LayerKind::Objects, LayerKind::Overhead];
+    let mut grids = vec![vec![0; tiled.width * tiled.height]; kinds.len()];
+    for layer in tiled.layers.iter() {
+        if let TiledLayer::Tiles { name, data } = layer {
+            let kind = match name.to_lowercase().as_str() {
+                "objects" => 1,
+                "overhead" => 2,
+                _ => 0,
+            };
+            let gids = &mut grids[kind];
+            if data.len() != gids.len() {
+                return Err("tile layers have to use the CSV layer format".to_string());
+            }
+            for (cell, gid) in gids.iter_mut().zip(data.iter()) {
+                if gid & GID_MASK != 0 {
+                    *cell = gid & GID_MASK;
+                }
+            }
+        }
+    }
+
+    let mut metadata = MapMetadata::default();
+    metadata.properties = leftover_properties(&tiled.properties, &[]).collect();
+    //weighted the same way chests count their items
+    if let Some(enemies) = string_property(&tiled.properties, "enemies") {
+        metadata.enemies = parse_items(&enemies)?
+            .into_iter()
+            .map(|(name, weight)| Ok((parse_enemy(&name)?, weight)))
+            .collect::<Result<_, String>>()?;
+    }
+
+    //objects change single tiles, which then get a legend entry of their own
+    //the tile on the objects layer if there is one, the ground below it otherwise
+    let mut changed: BTreeMap<(usize, i32, i32), TileDef> = BTreeMap::new();
+    for layer in tiled.layers.iter() {
+        let objects = match layer {
+            TiledLayer::Objects { objects } => objects,
+            _ => continue,
+        };
+        for object in objects.iter() {
+            let x = (object.x / tiled.tilewidth).floor() as i32;
+            let y = (object.y / tiled.tileheight).floor() as i32;
+            if x < 0 || y < 0 || x as usize >= tiled.width || y as usize >= tiled.height {
+                return Err(format!("object {} is outside of the map", object.name));
+            }
+            let cell = y as usize * tiled.width + x as usize;
+            let kind = if grids[1][cell] != 0 { 1 } else { 0 };
+            let gid = grids[kind][cell];
+            let def = changed.entry((kind, x, y)).or_insert_with(|| {
+                defs.get(&gid).cloned().unwrap_or_else(|| TileDef {
+                    glyph: Some(' '),
+                    ..Default::default()
+                })
+            });
+
+            let required = |name: &str| {
+                string_property(&object.properties, name).ok_or_else(|| {
+                    format!("{} {} needs a {} property", object.kind, object.name, name)
+                })
+            };
+            let known: &[&str] = match object.kind.as_str() {
+                "warp" => &["map", "entry"],
+                "sign" => &["text"],
+                "chest" => &["items"],
+                "npc" => &["npc"],
+                _ => &[],
+            };
+            def.properties
+                .extend(leftover_properties(&object.properties, known));
+            match object.kind.as_str() {
+                "spawn" => metadata.spawn = Some((x, y)),
+                "entry" => {
+                    metadata.entries.insert(object.name.clone(), (x, y));
+                }
+                "warp" => {
+                    def.trigger = Some(TileTrigger::Warp {
+                        map: required("map")?,
+                        entry: required("entry")?,
+                    });
+                }
+                "sign" => def.trigger = Some(TileTrigger::Sign(required("text")?)),
+                "door" => def.trigger = Some(TileTrigger::Door),
+                "chest" => {
+                    let items = string_property(&object.properties, "items").unwrap_or_default();
+                    metadata.chests.insert((x, y), parse_items(&items)?);
+                    def.trigger = Some(TileTrigger::Chest);
+                }
+                "npc" => def.npc = Some(parse_npc(&required("npc")?)?),
+                other => {
+                    return Err(format!(
+                        "object {} has unknown type {:?}",
+                        object.name, other
+                    ))
+                }
+            }
+        }
+    }
+
+    //an empty cell draws nothing, like a space in a text map
+    let mut keys = BTreeMap::new();
+    metadata.legend.insert(
+        EMPTY_GLYPH,
+        TileDef {
+            glyph: Some(EMPTY_GLYPH),
+            ..Default::default()
+        },
+    );
+    keys.insert(0, EMPTY_GLYPH);
+    let mut next_key = FIRST_LEGEND_KEY;
+    let mut new_key = |metadata: &mut MapMetadata, def: TileDef| {
+        let key = char::from_u32(next_key).expect("private use area ran out");
+        next_key += 1;
+        metadata.legend.insert(key, def);
+        key
+    };
+    for (gid, def) in defs {
+        keys.insert(gid, new_key(&mut metadata, def));
+    }
+
+    let mut layers = Vec::new();
+    for (kind, gids) in grids.iter().enumerate() {
+        //the objects and overhead layers are left out when nothing was drawn on them
+        if kind != 0 && gids.iter().all(|gid| *gid == 0) {
+            continue;
+        }
+        let mut rows = Vec::new();
+        for y in 0..tiled.height {
+            let mut row = String::new();
+            for x in 0..tiled.width {
+                let gid = gids[y * tiled.width + x];
+                let key = match changed.remove(&(kind, x as i32, y as i32)) {
+                    Some(def) => new_key(&mut metadata, def),
+                    None => *keys
+                        .get(&gid)
+                        .ok_or_else(|| format!("tile {} has no glyph property", gid))?,
+                };
+                row.push(key);
+            }
+            rows.push(row);
+        }
+        layers.push(MapLayer {
+            kind: kinds[kind],
+            rows,
+            first_line: 0,
+        });
+    }
+
+    Ok(MapAsset { metadata, layers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a 4x3 room saved from Tiled, grass in the middle that sways between two glyphs
+    const FIXTURE: &str = r##"{
+        "width": 4,
+        "height": 3,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "infinite": false,
+        "properties": [
+            {"name": "enemies", "type": "string", "value": "Bat:3, Ghost"},
+            {"name": "background", "type": "color", "value": "#ff000000"}
+        ],
+        "tilesets": [{
+            "firstgid": 1,
+            "tiles": [
+                {"id": 0, "properties": [
+                    {"name": "glyph", "type": "string", "value": "#"},
+                    {"name": "color", "type": "color", "value": "#ff808080"},
+                    {"name": "collider", "type": "bool", "value": true}
+                ]},
+                {"id": 1, "properties": [
+                    {"name": "glyph", "type": "string", "value": "."}
+                ]},
+                {"id": 2, "properties": [
+                    {"name": "glyph", "type": "string", "value": "\""},
+                    {"name": "encounter", "type": "float", "value": 0.25},
+                    {"name": "rustles", "type": "bool", "value": true}
+                ], "animation": [
+                    {"tileid": 2, "duration": 500},
+                    {"tileid": 3, "duration": 300}
+                ]},
+                {"id": 3, "properties": [
+                    {"name": "glyph", "type": "string", "value": "'"}
+                ]}
+            ]
+        }],
+        "layers": [
+            {"type": "tilelayer", "name": "ground", "data": [
+                1, 1, 1, 1,
+                1, 3, 3, 1,
+                1, 1, 1, 1
+            ]},
+            {"type": "objectgroup", "objects": [
+                {"name": "start", "type": "spawn", "x": 16, "y": 16},
+                {"name": "cave", "type": "entry", "x": 32, "y": 16},
+                {"name": "stairs", "type": "warp", "x": 40, "y": 20, "properties": [
+                    {"name": "map", "type": "string", "value": "cave.map.txt"},
+                    {"name": "entry", "type": "string", "value": "stairs"}
+                ]},
+                {"name": "welcome", "class": "sign", "x": 0, "y": 0, "properties": [
+                    {"name": "text", "type": "string", "value": "Welcome!"},
+                    {"name": "author", "type": "string", "value": "Mayor"}
+                ]}
+            ]}
+        ]
+    }"##;
+
+    fn ground_def(map: &MapAsset, x: i32, y: i32) -> TileDef {
+        map.defs_at(x, y)
+            .next()
+            .and_then(|(_, def)| def.cloned())
+            .expect("tile has a legend entry")
+    }
+
+    #[test]
+    fn fixture_imports() {
+        let map = import_tiled(FIXTURE.as_bytes()).expect("fixture imports");
+
+        //nothing was drawn on the objects or overhead layers
+        assert_eq!(map.layers.len(), 1);
+        assert_eq!(map.layers[0].rows.len(), 3);
+        assert_eq!(map.metadata.spawn, Some((1, 1)));
+        assert_eq!(map.metadata.entries["cave"], (2, 1));
+        assert_eq!(
+            map.metadata.enemies,
+            vec![(EnemyType::Bat, 3), (EnemyType::Ghost, 1)]
+        );
+
+        let wall = ground_def(&map, 3, 1);
+        assert_eq!(wall.glyph, Some('#'));
+        assert!(wall.collider);
+        assert!((wall.color.0 - 128.0 / 255.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn objects_become_triggers() {
+        let map = import_tiled(FIXTURE.as_bytes()).expect("fixture imports");
+
+        //the warp keeps the animated grass it was placed on
+        let warp = ground_def(&map, 2, 1);
+        assert_eq!(warp.glyph, Some('"'));
+        assert!(warp.animation.is_some());
+        assert!(matches!(
+            warp.trigger,
+            Some(TileTrigger::Warp { ref map, ref entry }) if map == "cave.map.txt" && entry == "stairs"
+        ));
+
+        let sign = ground_def(&map, 0, 0);
+        assert_eq!(sign.glyph, Some('#'));
+        assert!(matches!(sign.trigger, Some(TileTrigger::Sign(ref text)) if text == "Welcome!"));
+
+        //only the tiles with objects on them got a legend entry of their own
+        assert!(ground_def(&map, 1, 0).trigger.is_none());
+    }
+
+    #[test]
+    fn animation_frames_become_glyphs() {
+        let map = import_tiled(FIXTURE.as_bytes()).expect("fixture imports");
+
+        let grass = ground_def(&map, 1, 1);
+        assert_eq!(grass.encounter, Some(0.25));
+        match grass.animation {
+            Some(TileAnimation::Glyphs { glyphs, frame_time }) => {
+                assert_eq!(glyphs, vec!['"', '\'']);
+                assert!((frame_time - 0.4).abs() < f32::EPSILON);
+            }
+            _ => panic!("grass is not animated"),
+        }
+    }
+
+    #[test]
+    fn custom_properties_carry_over() {
+        let map = import_tiled(FIXTURE.as_bytes()).expect("fixture imports");
+
+        assert_eq!(map.metadata.properties["background"], "#ff000000");
+        let grass = ground_def(&map, 1, 1);
+        assert_eq!(grass.properties["rustles"], "true");
+        assert!(!grass.properties.contains_key("glyph"));
+        //the sign keeps the properties of its tile next to its own
+        let sign = ground_def(&map, 0, 0);
+        assert_eq!(sign.properties["author"], "Mayor");
+        assert!(!sign.properties.contains_key("text"));
+        //objects only change their own tile
+        assert!(!ground_def(&map, 1, 0).properties.contains_key("author"));
+    }
+
+    #[test]
+    fn only_the_import_checks_the_spawn() {
+        let json = FIXTURE.replace("\"type\": \"spawn\"", "\"type\": \"entry\"");
+        let map = read_tiled(json.as_bytes()).expect("map reads without a spawn");
+        assert_eq!(map.metadata.spawn, None);
+        let err = import_tiled(json.as_bytes())
+            .err()
+            .expect("missing spawn is an error");
+        assert_eq!(
+            err,
+            "no spawn point, add spawn: (column, row) to the header"
+        );
+    }
+
+    #[test]
+    fn unknown_object_type_is_an_error() {
+        let json = FIXTURE.replace("\"class\": \"sign\"", "\"class\": \"lever\"");
+        let err = import_tiled(json.as_bytes())
+            .err()
+            .expect("unknown object is an error");
+        assert_eq!(err, "object welcome has unknown type \"lever\"");
+    }
+}
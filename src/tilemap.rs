@@ -1,33 +1,418 @@
-use bevy::prelude::*;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
 };
+use ron::extensions::Extensions;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt};
 
 use crate::{
     ascii::{spawn_ascii_sprite, AsciiSpriteSheet},
+    battle::EnemyType,
+    dungeon::DungeonFloor,
+    fadeout::create_fadeout,
+    graphics::{spawn_npc_sprite, CharacterSheet},
+    interaction::{
+        chest_flag, door_flag, Chest, Door, Interactable, Sign, OPEN_CHEST_GLYPH, OPEN_DOOR_GLYPH,
+    },
     npc::Npc,
-    GameState, TILE_SIZE,
+    player::Player,
+    save::{AutosaveEvent, PendingLoad},
+    story::StoryFlags,
+    tile_chunks::{spawn_tile_chunks, AsciiChunkMaterial, ChunkTile},
+    GameState, CLEAR, TILE_SIZE,
 };
 
 #[derive(Component)]
 pub struct Map;
 
+pub struct TileMapPlugin;
+
+//something that happens when the player interacts with the tile
+#[derive(Clone, Deserialize)]
+pub enum TileTrigger {
+    Sign(String),
+    Door,
+    //contents come from the chests section of the header
+    Chest,
+    //stepping on the tile takes the player to an entry point of another map
+    Warp { map: String, entry: String },
+}
+
+#[derive(Component, Clone)]
+pub struct Warp {
+    pub map: String,
+    pub entry: String,
+}
+
+//what a glyph in the rows of a map stands for
+#[derive(Clone, Deserialize)]
+pub struct TileDef {
+    //glyph drawn from Ascii.png, the map glyph itself when missing
+    #[serde(default)]
+    pub glyph: Option<char>,
+    #[serde(default = "default_tile_color")]
+    pub color: (f32, f32, f32),
+    #[serde(default)]
+    pub collider: bool,
+    //chance of a random battle for every step taken on the tile
+    #[serde(default)]
+    pub encounter: Option<f32>,
+    //drawn with the npc sprite instead of a glyph, always blocks the way
+    #[serde(default)]
+    pub npc: Option<Npc>,
+    #[serde(default)]
+    pub trigger: Option<TileTrigger>,
+    //overhead tiles only, see through it while the player is underneath
+    #[serde(default)]
+    pub fade: bool,
+    //only tiles drawn in chunks move, the ones with triggers or npcs stay still
+    #[serde(default)]
+    pub animation: Option<TileAnimation>,
+    //free form values, custom properties of Tiled tiles and of the objects placed on them end up here
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+//every tile of the glyph runs the same animation, each from its own point so a field does not blink all at once
+#[derive(Clone, Deserialize)]
+pub enum TileAnimation {
+    //shows the glyphs one after the other
+    Glyphs { glyphs: Vec<char>, frame_time: f32 },
+    //the color goes over to this one and back every period
+    Pulse { color: (f32, f32, f32), period: f32 },
+}
+
+impl Default for TileDef {
+    fn default() -> Self {
+        TileDef {
+            glyph: None,
+            color: default_tile_color(),
+            collider: false,
+            encounter: None,
+            npc: None,
+            trigger: None,
+            fade: false,
+            animation: None,
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_tile_color() -> (f32, f32, f32) {
+    (0.9, 0.9, 0.9)
+}
+
+//RON header above a "---" line in the map file, things that do not fit in a single glyph
+#[derive(Default, Deserialize)]
+pub struct MapMetadata {
+    //every glyph used in the rows has to be listed here
+    #[serde(default)]
+    pub legend: BTreeMap<char, TileDef>,
+    //what each chest holds, keyed by the column and row of its glyph
+    #[serde(default)]
+    pub chests: BTreeMap<(i32, i32), Vec<(String, usize)>>,
+    //where a new game starts when this is the first map, every map needs one
+    #[serde(default)]
+    pub spawn: Option<(i32, i32)>,
+    //named tiles where warps from other maps put the player
+    #[serde(default)]
+    pub entries: BTreeMap<String, (i32, i32)>,
+    //who shows up in random battles on this map and how often, like [(Bat, 3), (Ghost, 1)]
+    #[serde(default)]
+    pub enemies: Vec<(EnemyType, usize)>,
+    //free form values, custom properties of imported Tiled maps end up here
+    //"background" is the color around the map and behind empty tiles, like "#000000"
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+//ground and objects work the same, objects just draw on top
+//overhead tiles draw above the player and are only looks, they never block or trigger anything
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LayerKind {
+    Ground,
+    Objects,
+    Overhead,
+}
+
+impl LayerKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "" | "ground" => Some(LayerKind::Ground),
+            "objects" => Some(LayerKind::Objects),
+            "overhead" => Some(LayerKind::Overhead),
+            _ => None,
+        }
+    }
+
+    fn z(&self) -> f32 {
+        match self {
+            LayerKind::Ground => 100.0,
+            LayerKind::Objects => 200.0,
+            LayerKind::Overhead => 600.0,
+        }
+    }
+}
+
+//in every layer but the ground a space is a hole showing what is below
+pub const EMPTY_GLYPH: char = ' ';
+
+pub struct MapLayer {
+    pub kind: LayerKind,
+    pub rows: Vec<String>,
+    //line of the file the first row is on
+    pub first_line: usize,
+}
+
+//a map file as loaded by the asset server, the header and the layers of glyphs, ground first
+#[derive(TypeUuid)]
+#[uuid = "5b7c1d3e-6f0a-4c8e-9a2d-3e41f7b8c960"]
+pub struct MapAsset {
+    pub metadata: MapMetadata,
+    pub layers: Vec<MapLayer>,
+}
+
+impl MapAsset {
+    //legend entries of the tiles the player would stand on, holes left out
+    pub fn defs_at(&self, x: i32, y: i32) -> impl Iterator<Item = (char, Option<&TileDef>)> {
+        let (x, y) = (usize::try_from(x).ok(), usize::try_from(y).ok());
+        self.layers
+            .iter()
+            .filter(|layer| layer.kind != LayerKind::Overhead)
+            .filter_map(move |layer| {
+                let glyph = layer.rows.get(y?)?.chars().nth(x?)?;
+                let hole = layer.kind != LayerKind::Ground && glyph == EMPTY_GLYPH;
+                (!hole).then(|| (glyph, self.metadata.legend.get(&glyph)))
+            })
+    }
+}
+
+//see-through overhead tile, faded by fade_overhead_tiles
 #[derive(Component)]
-pub struct EncounterSpawner;
+struct FadingOverhead;
 
-pub struct TileMapPlugin;
+const OVERHEAD_FADED_ALPHA: f32 = 0.3;
+
+//why a map file could not be loaded, logged by the asset server
+#[derive(Debug)]
+pub struct MapError {
+    path: String,
+    message: String,
+}
+
+impl MapError {
+    pub fn new(path: &str, message: impl fmt::Display) -> Self {
+        MapError {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not load map {}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for MapError {}
+
+#[derive(Default)]
+struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let contents = std::str::from_utf8(bytes).map_err(|err| MapError::new(&path, err))?;
+            let map = parse_map(contents).map_err(|message| MapError::new(&path, message))?;
+            load_context.set_default_asset(LoadedAsset::new(map));
+            Ok(())
+        })
+    }
+
+    //only *.map.txt, other text files in the assets folder are not maps
+    fn extensions(&self) -> &[&str] {
+        &["map.txt"]
+    }
+}
+
+//handle of the map file in CurrentMap, the map is spawned again whenever the file is (re)loaded
+#[derive(Default)]
+struct LoadedMap {
+    handle: Handle<MapAsset>,
+    needs_spawn: bool,
+    //where to put the player once the map is spawned, loading a save leaves the player alone
+    arrival: Option<Arrival>,
+    //the map a warp started from, still spawned until the new one is, so a broken warp can go back to it
+    origin: Option<WarpOrigin>,
+}
+
+struct WarpOrigin {
+    map: String,
+    handle: Handle<MapAsset>,
+}
+
+impl LoadedMap {
+    //the player stays on the map they warped from and gets control back
+    fn cancel_warp(&mut self, current_map: &mut CurrentMap, player: &mut Player) {
+        if let Some(origin) = self.origin.take() {
+            current_map.0 = origin.map;
+            self.handle = origin.handle;
+        }
+        self.needs_spawn = false;
+        self.arrival = None;
+        player.active = true;
+    }
+}
+
+enum Arrival {
+    Spawn,
+    Entry(String),
+}
+
+//the warp the player stepped on, taken once the screen is dark
+#[derive(Default)]
+struct PendingWarp(Option<Warp>);
 
 #[derive(Component)]
 pub struct TileCollider;
 
+//column and row of a tile in the map file, rows grow downwards
+#[derive(Component, Clone, Copy)]
+pub struct TilePosition(pub IVec2);
+
+//walls and encounter rates of the whole map, most tiles are only drawn in a chunk and have no entity to ask
+#[derive(Clone, Copy, Default)]
+struct Terrain {
+    collider: bool,
+    //chance of a random battle for every step taken on this tile
+    encounter: Option<f32>,
+}
+
+//answers "what is at this tile" without walking every tile entity, kept in sync as tiles come and go
+#[derive(Default)]
+pub struct TileGrid {
+    cells: HashMap<IVec2, Vec<Entity>>,
+    positions: HashMap<Entity, IVec2>,
+    width: i32,
+    terrain: Vec<Terrain>,
+}
+
+impl TileGrid {
+    pub fn at(&self, tile: IVec2) -> &[Entity] {
+        self.cells
+            .get(&tile)
+            .map(|entities| entities.as_slice())
+            .unwrap_or(&[])
+    }
+
+    //the tile and its eight neighbours
+    pub fn around(&self, tile: IVec2) -> impl Iterator<Item = Entity> + '_ {
+        (-1..=1)
+            .flat_map(move |y| (-1..=1).map(move |x| tile + IVec2::new(x, y)))
+            .flat_map(move |tile| self.at(tile).iter().copied())
+    }
+
+    //blocked by a wall drawn in a chunk, tiles with an entity use TileCollider instead so doors can open
+    pub fn blocks(&self, tile: IVec2) -> bool {
+        self.terrain(tile).map_or(false, |terrain| terrain.collider)
+    }
+
+    pub fn encounter_rate(&self, tile: IVec2) -> Option<f32> {
+        self.terrain(tile).and_then(|terrain| terrain.encounter)
+    }
+
+    fn terrain(&self, tile: IVec2) -> Option<&Terrain> {
+        if tile.x < 0 || tile.y < 0 || tile.x >= self.width {
+            return None;
+        }
+        self.terrain.get((tile.y * self.width + tile.x) as usize)
+    }
+
+    fn insert(&mut self, entity: Entity, tile: IVec2) {
+        self.remove(entity);
+        self.cells.entry(tile).or_default().push(entity);
+        self.positions.insert(entity, tile);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(tile) = self.positions.remove(&entity) {
+            if let Some(entities) = self.cells.get_mut(&tile) {
+                entities.retain(|other| *other != entity);
+                if entities.is_empty() {
+                    self.cells.remove(&tile);
+                }
+            }
+        }
+    }
+}
+
+pub fn world_to_tile(translation: Vec3) -> IVec2 {
+    IVec2::new(
+        (translation.x / TILE_SIZE).round() as i32,
+        (-translation.y / TILE_SIZE).round() as i32,
+    )
+}
+
+//enemies of the map the player is on with their weights, set whenever a map is spawned
+#[derive(Default)]
+pub struct EnemyTable(pub Vec<(EnemyType, usize)>);
+
+//file name of the map the player is on, relative to the assets folder
+pub struct CurrentMap(pub String);
+
+impl Default for CurrentMap {
+    fn default() -> Self {
+        CurrentMap("world.map.txt".to_string())
+    }
+}
+
 impl Plugin for TileMapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_resume(GameState::Overworld).with_system(show_map))
+        app.init_resource::<CurrentMap>()
+            .init_resource::<TileGrid>()
+            .init_resource::<LoadedMap>()
+            .init_resource::<PendingWarp>()
+            .init_resource::<EnemyTable>()
+            .add_asset::<MapAsset>()
+            .init_asset_loader::<MapLoader>()
+            //runs in every state so edits made during a battle are not missed
+            .add_system(watch_map_file)
+            //after the commands of the update stage are applied, so new tiles are already in the world
+            .add_system_to_stage(CoreStage::PostUpdate, update_tile_grid)
+            .add_system_set(SystemSet::on_resume(GameState::Overworld).with_system(show_map))
             .add_system_set(SystemSet::on_pause(GameState::Overworld).with_system(hide_map))
             .add_system_set(
-                SystemSet::on_enter(GameState::Overworld).with_system(create_simple_map),
-            );
+                SystemSet::on_enter(GameState::Overworld).with_system(load_map.after("load")),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Overworld)
+                    .with_system(spawn_map)
+                    .with_system(check_warps.after("movement"))
+                    .with_system(fade_overhead_tiles.after("movement")),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::Warp).with_system(start_warp));
+    }
+}
+
+fn update_tile_grid(
+    mut grid: ResMut<TileGrid>,
+    tile_query: Query<(Entity, &TilePosition), Changed<TilePosition>>,
+    removed: RemovedComponents<TilePosition>,
+) {
+    for entity in removed.iter() {
+        grid.remove(entity);
+    }
+    for (entity, position) in tile_query.iter() {
+        grid.insert(entity, position.0);
     }
 }
 
@@ -57,46 +442,549 @@ fn show_map(
     }
 }
 
-fn create_simple_map(mut commands: Commands, ascii: Res<AsciiSpriteSheet>) {
-    let file = File::open("assets/map.txt").expect("No map file found");
+//what the game loads, a map that reads fine, only uses glyphs from its legend and passes validate_map
+pub fn parse_map(contents: &str) -> Result<MapAsset, String> {
+    let map = read_map(contents)?;
+    if let Some(problem) = unknown_glyphs(&map).into_iter().next() {
+        return Err(problem);
+    }
+    validate_map(&map)?;
+    Ok(map)
+}
+
+//splits the metadata header from the layers of glyphs without checking them
+//the header ends at the first "---" line, "--- objects" and "--- overhead" lines start the other layers
+//a map without any "---" is all ground
+pub fn read_map(contents: &str) -> Result<MapAsset, String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let separators: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with("---"))
+        .map(|(i, _)| i)
+        .collect();
+    let metadata = match separators.first() {
+        //spawn: (2, 2) reads better than spawn: Some((2, 2))
+        Some(first) => ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str::<MapMetadata>(&lines[..*first].join("\n"))
+            .map_err(|err| format!("bad header: {}", err))?,
+        None => MapMetadata::default(),
+    };
+
+    //every layer is the lines after its separator, up to the next one
+    let mut sections = Vec::new();
+    if separators.is_empty() {
+        sections.push((LayerKind::Ground, 0, lines.len()));
+    }
+    for (i, separator) in separators.iter().enumerate() {
+        let name = lines[*separator].trim_start().trim_start_matches('-');
+        let kind = LayerKind::from_name(name)
+            .ok_or_else(|| format!("unknown layer {:?} on line {}", name.trim(), separator + 1))?;
+        let end = separators.get(i + 1).copied().unwrap_or(lines.len());
+        sections.push((kind, separator + 1, end));
+    }
+
+    let layers = sections
+        .into_iter()
+        .map(|(kind, start, end)| MapLayer {
+            kind,
+            rows: lines[start..end]
+                .iter()
+                .map(|row| row.to_string())
+                .collect(),
+            first_line: start + 1,
+        })
+        .collect();
+
+    Ok(MapAsset { metadata, layers })
+}
+
+//every glyph missing from the legend, named by the line of the file it is on
+pub fn unknown_glyphs(map: &MapAsset) -> Vec<String> {
+    let mut problems = Vec::new();
+    for layer in map.layers.iter() {
+        for (y, row) in layer.rows.iter().enumerate() {
+            for (x, glyph) in row.chars().enumerate() {
+                let hole = layer.kind != LayerKind::Ground && glyph == EMPTY_GLYPH;
+                if !hole && !map.metadata.legend.contains_key(&glyph) {
+                    problems.push(format!(
+                        "unknown glyph {:?} on line {}, column {}, add it to the legend",
+                        glyph,
+                        layer.first_line + y,
+                        x + 1
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
+//the player has to be able to stand on the spawn point and on every entry point
+pub fn validate_map(map: &MapAsset) -> Result<(), String> {
+    let spawn = map
+        .metadata
+        .spawn
+        .ok_or_else(|| "no spawn point, add spawn: (column, row) to the header".to_string())?;
+    let points = std::iter::once(("spawn point".to_string(), spawn)).chain(
+        map.metadata
+            .entries
+            .iter()
+            .map(|(name, tile)| (format!("entry point {}", name), *tile)),
+    );
+    for (name, (x, y)) in points {
+        let mut defs = map.defs_at(x, y).peekable();
+        if defs.peek().is_none() {
+            return Err(format!("{} ({}, {}) is outside of the map", name, x, y));
+        }
+        for (glyph, def) in defs {
+            if def.map_or(false, |def| def.collider || def.npc.is_some()) {
+                return Err(format!("{} ({}, {}) is blocked by {:?}", name, x, y, glyph));
+            }
+        }
+    }
+
+    for (glyph, def) in map.metadata.legend.iter() {
+        //npcs are drawn from characters.png, everything else is a glyph of the atlas
+        let drawn = def.glyph.unwrap_or(*glyph);
+        if def.npc.is_none() && drawn as u32 >= 256 {
+            return Err(format!(
+                "{:?} is drawn as {:?}, Ascii.png only has 256 glyphs, pick one with glyph:",
+                glyph, drawn
+            ));
+        }
+        match &def.animation {
+            Some(TileAnimation::Glyphs { glyphs, frame_time }) => {
+                if glyphs.is_empty() || *frame_time <= 0.0 {
+                    return Err(format!(
+                        "animation of {:?} needs glyphs and a frame_time above 0",
+                        glyph
+                    ));
+                }
+                if let Some(frame) = glyphs.iter().find(|frame| **frame as u32 >= 256) {
+                    return Err(format!(
+                        "animation of {:?} uses {:?}, Ascii.png only has 256 glyphs",
+                        glyph, frame
+                    ));
+                }
+            }
+            Some(TileAnimation::Pulse { period, .. }) if *period <= 0.0 => {
+                return Err(format!("pulse of {:?} needs a period above 0", glyph));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(background) = map.metadata.properties.get("background") {
+        parse_color(background).map_err(|err| format!("background: {}", err))?;
+    }
+    Ok(())
+}
+
+//colors written as #rrggbb, or #aarrggbb the way Tiled writes them
+pub fn parse_color(text: &str) -> Result<(f32, f32, f32), String> {
+    let hex = text.trim_start_matches('#');
+    let rgb = match hex.len() {
+        6 => hex,
+        8 => &hex[2..],
+        _ => return Err(format!("bad color {}", text)),
+    };
+    let channel = |i: usize| {
+        u8::from_str_radix(&rgb[i..i + 2], 16)
+            .map(|value| value as f32 / 255.0)
+            .map_err(|_| format!("bad color {}", text))
+    };
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+//most maps are files in the assets folder, dungeon floors are generated from their name instead
+//a file that fails shows up later in its load state, a floor that fails to generate right here
+fn map_handle(
+    name: &str,
+    assets: &AssetServer,
+    maps: &mut Assets<MapAsset>,
+) -> Result<Handle<MapAsset>, String> {
+    match DungeonFloor::from_name(name) {
+        Some(floor) => floor
+            .generate()
+            .map(|map| maps.add(map))
+            .map_err(|err| format!("Could not generate {}: {}", name, err)),
+        None => Ok(assets.load(name)),
+    }
+}
+
+fn load_map(
+    mut loaded_map: ResMut<LoadedMap>,
+    mut current_map: ResMut<CurrentMap>,
+    assets: Res<AssetServer>,
+    mut maps: ResMut<Assets<MapAsset>>,
+    pending_load: Option<Res<PendingLoad>>,
+) {
+    //a save already knows where the player stands
+    loaded_map.arrival = match pending_load {
+        Some(_) => None,
+        None => Some(Arrival::Spawn),
+    };
+    loaded_map.handle = match map_handle(&current_map.0, &assets, &mut maps) {
+        Ok(handle) => handle,
+        //there is no map to stay on yet, a save on a floor that does not generate starts over on the first map
+        Err(err) => {
+            error!("{}", err);
+            *current_map = CurrentMap::default();
+            loaded_map.arrival = Some(Arrival::Spawn);
+            assets.load(current_map.0.as_str())
+        }
+    };
+    loaded_map.needs_spawn = true;
+    loaded_map.origin = None;
+}
+
+fn check_warps(
+    mut commands: Commands,
+    mut player_query: Query<(&mut Player, &Transform)>,
+    warp_query: Query<&Warp>,
+    grid: Res<TileGrid>,
+    mut pending: ResMut<PendingWarp>,
+    mut last_tile: Local<Option<IVec2>>,
+    ascii: Res<AsciiSpriteSheet>,
+) {
+    let (mut player, transform) = player_query.single_mut();
+    if player.mid_step() {
+        return;
+    }
+    //only stepping onto a warp counts, standing on one after arriving does not
+    let tile = world_to_tile(transform.translation);
+    if last_tile.replace(tile) == Some(tile) || !player.active {
+        return;
+    }
+
+    if let Some(warp) = grid
+        .at(tile)
+        .iter()
+        .find_map(|entity| warp_query.get(*entity).ok())
+    {
+        player.active = false;
+        pending.0 = Some(warp.clone());
+        create_fadeout(&mut commands, Some(GameState::Warp), &ascii);
+    }
+}
+
+//the screen is dark here, the map is swapped and the overworld comes right back
+fn start_warp(
+    mut pending: ResMut<PendingWarp>,
+    mut loaded_map: ResMut<LoadedMap>,
+    mut current_map: ResMut<CurrentMap>,
+    assets: Res<AssetServer>,
+    mut maps: ResMut<Assets<MapAsset>>,
+    mut state: ResMut<State<GameState>>,
+    mut player_query: Query<&mut Player>,
+) {
+    if let Some(warp) = pending.0.take() {
+        let handle = match map_handle(&warp.map, &assets, &mut maps) {
+            Ok(handle) => handle,
+            Err(err) => {
+                error!("{}", err);
+                player_query.single_mut().active = true;
+                let _ = state.pop();
+                return;
+            }
+        };
+        loaded_map.origin = Some(WarpOrigin {
+            map: std::mem::replace(&mut current_map.0, warp.map),
+            handle: std::mem::replace(&mut loaded_map.handle, handle),
+        });
+        loaded_map.needs_spawn = true;
+        loaded_map.arrival = Some(Arrival::Entry(warp.entry));
+    }
+    let _ = state.pop();
+}
+
+fn watch_map_file(
+    mut loaded_map: ResMut<LoadedMap>,
+    mut events: EventReader<AssetEvent<MapAsset>>,
+    mut current_map: ResMut<CurrentMap>,
+    assets: Res<AssetServer>,
+    mut player_query: Query<&mut Player>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if *handle == loaded_map.handle {
+                info!("Map {} changed on disk, respawning it", current_map.0);
+                loaded_map.needs_spawn = true;
+            }
+        }
+    }
+
+    //the reason was already logged by the asset server, stop waiting for a map that never comes
+    if loaded_map.needs_spawn && assets.get_load_state(&loaded_map.handle) == LoadState::Failed {
+        error!("Map {} failed to load", current_map.0);
+        loaded_map.needs_spawn = false;
+        if loaded_map.origin.is_some() {
+            if let Ok(mut player) = player_query.get_single_mut() {
+                loaded_map.cancel_warp(&mut current_map, &mut player);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_map(
+    mut commands: Commands,
+    mut loaded_map: ResMut<LoadedMap>,
+    maps: Res<Assets<MapAsset>>,
+    map_query: Query<Entity, With<Map>>,
+    ascii: Res<AsciiSpriteSheet>,
+    characters: Res<CharacterSheet>,
+    mut current_map: ResMut<CurrentMap>,
+    story: Res<StoryFlags>,
+    mut player_query: Query<(&mut Player, &mut Transform)>,
+    mut autosave: EventWriter<AutosaveEvent>,
+    mut grid: ResMut<TileGrid>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_material: Res<AsciiChunkMaterial>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut enemy_table: ResMut<EnemyTable>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    if !loaded_map.needs_spawn {
+        return;
+    }
+    let map = match maps.get(&loaded_map.handle) {
+        Some(map) => map,
+        //still loading
+        None => return,
+    };
+    let (mut player, mut transform) = player_query.single_mut();
+
+    //a warp to an entry point the map does not have is turned down, the player would land anywhere
+    if let Some(Arrival::Entry(entry)) = &loaded_map.arrival {
+        if !map.metadata.entries.contains_key(entry) {
+            error!("Map {} has no entry point {}", current_map.0, entry);
+            loaded_map.cancel_warp(&mut current_map, &mut player);
+            return;
+        }
+    }
+    loaded_map.needs_spawn = false;
+    loaded_map.origin = None;
+
+    if let Some(arrival) = loaded_map.arrival.take() {
+        let tile = match arrival {
+            //validation made sure every map has one
+            Arrival::Spawn => map.metadata.spawn,
+            Arrival::Entry(entry) => {
+                //the warp took control away from the player
+                player.active = true;
+                autosave.send(AutosaveEvent);
+                map.metadata.entries.get(&entry).copied()
+            }
+        };
+        if let Some((x, y)) = tile {
+            transform.translation.x = x as f32 * TILE_SIZE;
+            transform.translation.y = -(y as f32) * TILE_SIZE;
+        }
+    }
+
+    for entity in map_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let metadata = &map.metadata;
+    let atlas = atlases
+        .get(&ascii.0)
+        .expect("ascii atlas is added at startup");
     let mut tiles = Vec::new();
 
-    for (y, line) in BufReader::new(file).lines().enumerate() {
-        if let Ok(line) = line {
+    //chunk tiles point at their animation by its place in this list
+    let (animated_glyphs, animations): (Vec<char>, Vec<TileAnimation>) = metadata
+        .legend
+        .iter()
+        .filter_map(|(glyph, def)| Some((*glyph, def.animation.clone()?)))
+        .unzip();
+
+    let width = map
+        .layers
+        .iter()
+        .flat_map(|layer| layer.rows.iter())
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    let height = map
+        .layers
+        .iter()
+        .map(|layer| layer.rows.len())
+        .max()
+        .unwrap_or(0);
+    let mut terrain = vec![Terrain::default(); width * height];
+
+    for layer in map.layers.iter() {
+        //tiles nothing interacts with go into the chunk meshes of the layer
+        let mut static_tiles = Vec::new();
+
+        for (y, line) in layer.rows.iter().enumerate() {
             for (x, char) in line.chars().enumerate() {
-                let color = match char {
-                    '#' => Color::rgb(0.7, 0.7, 0.7),
-                    '@' => Color::rgb(0.5, 0.5, 0.2),
-                    '~' => Color::rgb(0.2, 0.9, 0.2),
-                    _ => Color::rgb(0.9, 0.9, 0.9),
+                if layer.kind != LayerKind::Ground && char == EMPTY_GLYPH {
+                    continue;
+                }
+                //parse_map made sure every glyph has an entry
+                let def = &metadata.legend[&char];
+                let (r, g, b) = def.color;
+                //overhead tiles never block or trigger, they only need an entity to fade
+                let needs_entity = match layer.kind {
+                    LayerKind::Overhead => def.fade,
+                    _ => def.npc.is_some() || def.trigger.is_some(),
                 };
 
-                let tile = spawn_ascii_sprite(
-                    &mut commands,
-                    &ascii,
-                    char as usize,
-                    color,
-                    Vec3::new(x as f32 * TILE_SIZE, -(y as f32) * TILE_SIZE, 100.0),
-                    Vec3::splat(1.0),
+                if layer.kind != LayerKind::Overhead {
+                    let cell = &mut terrain[y * width + x];
+                    cell.collider |= def.collider && !needs_entity;
+                    if def.encounter.is_some() {
+                        cell.encounter = def.encounter;
+                    }
+                }
+
+                if !needs_entity {
+                    static_tiles.push(ChunkTile {
+                        tile: IVec2::new(x as i32, y as i32),
+                        index: def.glyph.unwrap_or(char) as usize,
+                        color: Color::rgb(r, g, b),
+                        animation: animated_glyphs.iter().position(|glyph| *glyph == char),
+                    });
+                    continue;
+                }
+
+                let translation = Vec3::new(
+                    x as f32 * TILE_SIZE,
+                    -(y as f32) * TILE_SIZE,
+                    layer.kind.z(),
                 );
-                if char == '#' {
-                    commands.entity(tile).insert(TileCollider);
+
+                if layer.kind == LayerKind::Overhead {
+                    let tile = spawn_ascii_sprite(
+                        &mut commands,
+                        &ascii,
+                        def.glyph.unwrap_or(char) as usize,
+                        Color::rgb(r, g, b),
+                        translation,
+                        Vec3::splat(1.0),
+                    );
+                    commands
+                        .entity(tile)
+                        .insert(TilePosition(IVec2::new(x as i32, y as i32)))
+                        .insert(FadingOverhead);
+                    tiles.push(tile);
+                    continue;
                 }
 
-                if char == '~' {
-                    commands.entity(tile).insert(EncounterSpawner);
+                let is_chest = matches!(def.trigger, Some(TileTrigger::Chest));
+                let is_door = matches!(def.trigger, Some(TileTrigger::Door));
+
+                //chests and doors opened before, even in an older session, come back already open
+                let chest_flag = chest_flag(&current_map.0, x, y);
+                let door_flag = door_flag(&current_map.0, x, y);
+                let opened = is_chest && story.is_set(&chest_flag);
+                let door_open = is_door && story.is_set(&door_flag);
+                let glyph = if opened {
+                    OPEN_CHEST_GLYPH
+                } else if door_open {
+                    OPEN_DOOR_GLYPH
+                } else {
+                    def.glyph.unwrap_or(char)
+                };
+
+                let tile = match def.npc {
+                    Some(npc) => {
+                        spawn_npc_sprite(&mut commands, &characters, npc.appearance(), translation)
+                    }
+                    None => spawn_ascii_sprite(
+                        &mut commands,
+                        &ascii,
+                        glyph as usize,
+                        Color::rgb(r, g, b),
+                        translation,
+                        Vec3::splat(1.0),
+                    ),
+                };
+                commands
+                    .entity(tile)
+                    .insert(TilePosition(IVec2::new(x as i32, y as i32)));
+                if def.collider && !door_open {
+                    commands.entity(tile).insert(TileCollider);
                 }
 
-                if char == '@' {
+                if let Some(npc) = def.npc {
                     commands
                         .entity(tile)
                         .insert(TileCollider)
-                        .insert(Npc::Healer);
+                        .insert(npc)
+                        .insert(Interactable::Talk);
+                }
+
+                match &def.trigger {
+                    Some(TileTrigger::Sign(text)) => {
+                        commands
+                            .entity(tile)
+                            .insert(Sign(text.clone()))
+                            .insert(Interactable::ReadSign);
+                    }
+                    Some(TileTrigger::Door) => {
+                        if !door_open {
+                            commands
+                                .entity(tile)
+                                .insert(Door { flag: door_flag })
+                                .insert(Interactable::UseDoor);
+                        }
+                    }
+                    Some(TileTrigger::Warp { map, entry }) => {
+                        commands.entity(tile).insert(Warp {
+                            map: map.clone(),
+                            entry: entry.clone(),
+                        });
+                    }
+                    Some(TileTrigger::Chest) | None => {}
+                }
+
+                if is_chest {
+                    if !opened {
+                        let contents = metadata
+                            .chests
+                            .get(&(x as i32, y as i32))
+                            .cloned()
+                            .unwrap_or_default();
+                        commands
+                            .entity(tile)
+                            .insert(Chest {
+                                contents,
+                                flag: chest_flag,
+                            })
+                            .insert(Interactable::OpenChest);
+                    }
                 }
                 tiles.push(tile);
             }
         }
+
+        tiles.extend(spawn_tile_chunks(
+            &mut commands,
+            &mut meshes,
+            &chunk_material,
+            atlas,
+            &static_tiles,
+            &animations,
+            layer.kind.z(),
+        ));
     }
+    grid.width = width as i32;
+    grid.terrain = terrain;
+    enemy_table.0 = metadata.enemies.clone();
+    clear_color.0 = match metadata
+        .properties
+        .get("background")
+        .and_then(|background| parse_color(background).ok())
+    {
+        Some((r, g, b)) => Color::rgb(r, g, b),
+        None => CLEAR,
+    };
 
     commands
         //bevy now on 0.8 added the visibility inheritance. check it out here https://bevyengine.org/news/bevy-0-8/#spatialbundle-and-visibilitybundle
@@ -109,3 +997,186 @@ fn create_simple_map(mut commands: Commands, ascii: Res<AsciiSpriteSheet>) {
         .insert(GlobalTransform::default()) */
         .push_children(&tiles);
 }
+
+//roof edges and tree tops close to the player turn see-through, and back once the player leaves
+fn fade_overhead_tiles(
+    player_query: Query<&Transform, With<Player>>,
+    mut tile_query: Query<(&TilePosition, &mut TextureAtlasSprite), With<FadingOverhead>>,
+    time: Res<Time>,
+) {
+    let player_tile = world_to_tile(player_query.single().translation);
+    let step = time.delta_seconds() * 4.0;
+    for (position, mut sprite) in tile_query.iter_mut() {
+        let offset = (position.0 - player_tile).abs();
+        let target = if offset.max_element() <= 1 {
+            OVERHEAD_FADED_ALPHA
+        } else {
+            1.0
+        };
+        let alpha = sprite.color.a();
+        sprite
+            .color
+            .set_a(alpha + (target - alpha).clamp(-step, step));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = r#"(
+    legend: {
+        '#': (color: (0.5, 0.5, 0.5), collider: true),
+        '.': (),
+        '$': (collider: true, trigger: Some(Chest)),
+        '^': (fade: true),
+    },
+    chests: {
+        (2, 1): [("Repel", 2)],
+    },
+    spawn: (1, 1),
+    entries: {
+        "door": (3, 1),
+    },
+)"#;
+
+    fn map_text(layers: &str) -> String {
+        format!("{}\n{}", HEADER, layers)
+    }
+
+    #[test]
+    fn valid_map_parses_into_layers() {
+        let map = parse_map(&map_text(
+            "---\n#####\n#...#\n#####\n--- objects\n\n  $\n--- overhead\n ^^^",
+        ))
+        .expect("map parses");
+        let header_lines = HEADER.lines().count();
+
+        let kinds: Vec<LayerKind> = map.layers.iter().map(|layer| layer.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![LayerKind::Ground, LayerKind::Objects, LayerKind::Overhead]
+        );
+        assert_eq!(map.layers[0].rows, vec!["#####", "#...#", "#####"]);
+        assert_eq!(map.layers[0].first_line, header_lines + 2);
+        assert_eq!(map.layers[1].first_line, header_lines + 6);
+        assert_eq!(map.metadata.spawn, Some((1, 1)));
+        assert_eq!(map.metadata.entries["door"], (3, 1));
+        assert_eq!(map.metadata.chests[&(2, 1)], vec![("Repel".to_string(), 2)]);
+        assert!(map.metadata.legend[&'#'].collider);
+        //objects cover the ground, overhead tiles are never stood on
+        let defs: Vec<char> = map.defs_at(2, 1).map(|(glyph, _)| glyph).collect();
+        assert_eq!(defs, vec!['.', '$']);
+    }
+
+    #[test]
+    fn map_without_separator_is_all_ground() {
+        let map = read_map("##\n..").expect("map reads");
+        assert_eq!(map.layers.len(), 1);
+        assert_eq!(map.layers[0].kind, LayerKind::Ground);
+        assert_eq!(map.layers[0].first_line, 1);
+        assert!(map.metadata.legend.is_empty());
+    }
+
+    #[test]
+    fn unknown_glyph_names_its_line_and_column() {
+        let text = map_text("---\n#####\n#.X.#\n#####");
+        let line = HEADER.lines().count() + 3;
+        let err = parse_map(&text).err().expect("unknown glyph is an error");
+        assert_eq!(
+            err,
+            format!(
+                "unknown glyph 'X' on line {}, column 3, add it to the legend",
+                line
+            )
+        );
+    }
+
+    #[test]
+    fn every_unknown_glyph_is_listed() {
+        let map = read_map(&map_text("---\n#X#\n--- objects\n Y\n   ")).expect("map reads");
+        let problems = unknown_glyphs(&map);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("'X'"));
+        assert!(problems[1].contains("'Y'"));
+    }
+
+    #[test]
+    fn unknown_layer_names_its_line() {
+        let err = read_map(&map_text("---\n#\n--- roof\n#"))
+            .err()
+            .expect("unknown layer is an error");
+        let line = HEADER.lines().count() + 3;
+        assert_eq!(err, format!("unknown layer \"roof\" on line {}", line));
+    }
+
+    #[test]
+    fn bad_header_is_an_error() {
+        let err = read_map("(legend: {'#': (collider: maybe)})\n---\n#")
+            .err()
+            .expect("bad header is an error");
+        assert!(err.starts_with("bad header:"), "{}", err);
+    }
+
+    #[test]
+    fn missing_spawn_is_an_error() {
+        let text = map_text("---\n#####\n#...#\n#####").replace("    spawn: (1, 1),\n", "");
+        let err = parse_map(&text).err().expect("missing spawn is an error");
+        assert_eq!(
+            err,
+            "no spawn point, add spawn: (column, row) to the header"
+        );
+    }
+
+    #[test]
+    fn blocked_spawn_is_an_error() {
+        let err = parse_map(&map_text("---\n#####\n##..#\n#####"))
+            .err()
+            .expect("blocked spawn is an error");
+        assert_eq!(err, "spawn point (1, 1) is blocked by '#'");
+    }
+
+    #[test]
+    fn entry_blocked_by_an_object_is_an_error() {
+        let err = parse_map(&map_text("---\n#####\n#...#\n#####\n--- objects\n\n   $"))
+            .err()
+            .expect("blocked entry is an error");
+        assert_eq!(err, "entry point door (3, 1) is blocked by '$'");
+    }
+
+    #[test]
+    fn entry_outside_of_the_map_is_an_error() {
+        let err = parse_map(&map_text("---\n###\n#.#\n###"))
+            .err()
+            .expect("entry outside of the map is an error");
+        assert_eq!(err, "entry point door (3, 1) is outside of the map");
+    }
+
+    #[test]
+    fn bad_background_is_an_error() {
+        let text = map_text("---\n#####\n#...#\n#####").replace(
+            "    spawn: (1, 1),",
+            "    spawn: (1, 1),\n    properties: {\"background\": \"dark\"},",
+        );
+        let err = parse_map(&text).err().expect("bad background is an error");
+        assert_eq!(err, "background: bad color dark");
+
+        let text = text.replace("dark", "#101018");
+        let map = parse_map(&text).expect("map with a background parses");
+        assert_eq!(map.metadata.properties["background"], "#101018");
+    }
+
+    #[test]
+    fn glyph_outside_of_the_atlas_needs_a_replacement() {
+        let text = map_text("---\n#####\n#...#\n#####")
+            .replace("'.': (),", "'.': (),\n        '█': (collider: true),");
+        let err = parse_map(&text).err().expect("wide glyph is an error");
+        assert!(err.contains("Ascii.png only has 256 glyphs"), "{}", err);
+
+        let text = text.replace(
+            "'█': (collider: true)",
+            "'█': (glyph: Some('#'), collider: true)",
+        );
+        assert!(parse_map(&text).is_ok());
+    }
+}